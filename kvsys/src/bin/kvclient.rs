@@ -166,13 +166,5 @@ fn check_key_size(slice: &[u8]) -> Result<Key, ClientError> {
 }
 
 fn check_value_size(slice: &[u8]) -> Result<Value, ClientError> {
-    if slice.len() < 256 {
-        let mut ret = [0; 256];
-        for i in 0..slice.len() {
-            ret[i] = slice[i];
-        }
-        Ok(Value::from_slice(&ret))
-    } else {
-        Value::from_slice_checked(slice).ok_or(ClientError::new("incorrect value size"))
-    }
+    Ok(Value::from_slice(slice))
 }
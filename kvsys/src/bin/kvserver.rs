@@ -1,6 +1,10 @@
-use clap::{Arg, App};
+use clap::{Arg, App, SubCommand, value_t};
 use kvsys::kvserver::{KVServerConfig, run_server};
+use kvsys::kvstorage::disklog;
+use kvsys::kvstorage::disklog::DiskLogCompactor;
 use env_logger;
+use std::path::Path;
+use std::process;
 
 fn main() {
     env_logger::init();
@@ -9,6 +13,31 @@ fn main() {
         .version("0.1")
         .author("ICEY <icey@icey.tech>")
         .about("The official server program making use of Project-KV kvstorage library")
+        .subcommand(SubCommand::with_name("upgrade")
+            .about("Migrates a data file (possibly headerless/legacy) to the current disk log format in place")
+            .arg(Arg::with_name("file")
+                .value_name("FILE")
+                .help("The data file to upgrade")
+                .required(true)))
+        .subcommand(SubCommand::with_name("compact")
+            .about("Compacts a data file down to its live set in place, following the bitcask approach \
+                   (see kvstorage::disklog::DiskLogCompactor); run this offline, the server must not \
+                   have the file open")
+            .arg(Arg::with_name("file")
+                .value_name("FILE")
+                .help("The data file to compact")
+                .required(true))
+            .arg(Arg::with_name("hint")
+                .long("hint")
+                .help("Also write a .hint sidecar mapping each surviving key to its byte offset")
+                .takes_value(false)))
+        .arg(Arg::with_name("config")
+            .short("c")
+            .long("config")
+            .value_name("FILE")
+            .help("Path to a TOML config file; settings also given on the command line or via \
+                   KVSERVER_* environment variables must not conflict with it")
+            .takes_value(true))
         .arg(Arg::with_name("port")
             .short("p")
             .long("port")
@@ -21,9 +50,102 @@ fn main() {
             .value_name("FILE")
             .help("Choose the file the server should write to or read from")
             .takes_value(true))
+        .arg(Arg::with_name("resp-port")
+            .long("resp-port")
+            .value_name("PORT")
+            .help("Also accept redis-protocol (RESP) connections on this port")
+            .takes_value(true))
+        .arg(Arg::with_name("tls-cert")
+            .long("tls-cert")
+            .value_name("FILE")
+            .help("Path to a PEM certificate (chain) used to terminate TLS connections")
+            .takes_value(true))
+        .arg(Arg::with_name("tls-key")
+            .long("tls-key")
+            .value_name("FILE")
+            .help("Path to the PEM private key matching --tls-cert")
+            .takes_value(true))
+        .arg(Arg::with_name("tls-port")
+            .long("tls-port")
+            .value_name("PORT")
+            .help("Port the TLS listener binds to, defaults to --port")
+            .takes_value(true))
+        .arg(Arg::with_name("ssl-only")
+            .long("ssl-only")
+            .help("Reject plaintext connections; only the TLS listener is bound")
+            .takes_value(false))
+        .arg(Arg::with_name("tls-client-ca")
+            .long("tls-client-ca")
+            .value_name("FILE")
+            .help("Path to a PEM file of CA certificates; when set, require every TLS client to \
+                   present a certificate signed by one of them (mutual TLS)")
+            .takes_value(true))
+        .arg(Arg::with_name("metrics-port")
+            .long("metrics-port")
+            .value_name("PORT")
+            .help("Serve Prometheus text exposition metrics over an HTTP listener on this port")
+            .takes_value(true))
+        .arg(Arg::with_name("raw-port")
+            .long("raw-port")
+            .value_name("PORT")
+            .help("Also accept plain VarInt length-framed connections (see kvserver::framing) on this port")
+            .takes_value(true))
+        .arg(Arg::with_name("max-in-flight-connections")
+            .long("max-in-flight-connections")
+            .value_name("N")
+            .help("Max connections queued for or being handled at once; the accept loop blocks \
+                   once this is reached (default 256)")
+            .takes_value(true))
+        .arg(Arg::with_name("cdc-kafka-brokers")
+            .long("cdc-kafka-brokers")
+            .value_name("BROKERS")
+            .help("Comma-separated Kafka bootstrap server list; set together with \
+                   --cdc-kafka-topic to stream every PUT/DEL mutation to Kafka")
+            .takes_value(true))
+        .arg(Arg::with_name("cdc-kafka-topic")
+            .long("cdc-kafka-topic")
+            .value_name("TOPIC")
+            .help("Kafka topic the change-data-capture sink publishes mutations to")
+            .takes_value(true))
+        .arg(Arg::with_name("cdc-buffer-size")
+            .long("cdc-buffer-size")
+            .value_name("N")
+            .help("Number of mutations the change-data-capture sink buffers before applying \
+                   --cdc-drop-on-overflow (default 1024)")
+            .takes_value(true))
+        .arg(Arg::with_name("cdc-drop-on-overflow")
+            .long("cdc-drop-on-overflow")
+            .value_name("BOOL")
+            .help("When the change-data-capture buffer is full: true drops the mutation, false \
+                   blocks request handling until there's room (default true)")
+            .takes_value(true))
         .get_matches();
 
-    let config = KVServerConfig::from_arg_matches(matches);
+    if let Some(upgrade_matches) = matches.subcommand_matches("upgrade") {
+        let file = value_t!(upgrade_matches, "file", String).unwrap_or_else(|e| e.exit());
+        if let Err(e) = disklog::upgrade(Path::new(&file)) {
+            eprintln!("failed upgrading '{}': {}", file, e);
+            process::exit(1);
+        }
+        println!("'{}' upgraded to the current disk log format", file);
+        return;
+    }
+
+    if let Some(compact_matches) = matches.subcommand_matches("compact") {
+        let file = value_t!(compact_matches, "file", String).unwrap_or_else(|e| e.exit());
+        let write_hint = compact_matches.is_present("hint");
+        if let Err(e) = DiskLogCompactor::compact(Path::new(&file), write_hint) {
+            eprintln!("failed compacting '{}': {}", file, e);
+            process::exit(1);
+        }
+        println!("'{}' compacted to its live set", file);
+        return;
+    }
+
+    let config = KVServerConfig::from_layered(matches).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
 
     run_server(config);
 }
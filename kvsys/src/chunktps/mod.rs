@@ -11,12 +11,12 @@
 //! ```no_run
 //!     use std::net::{TcpStream, TcpListener};
 //!     use std::thread;
-//!     use kvsys::chunktps::ChunktpConnection;
+//!     use kvsys::chunktps::ChunktpsConnection;
 //!     // ...
 //!     let tcp_listener = TcpListener::bind("127.0.0.1:4000").unwrap();
 //!     for tcp_stream in tcp_listener.incoming() {
 //!         let tcp_stream = tcp_stream.unwrap();
-//!         let mut chunktps = ChunktpConnection::new(tcp_stream);
+//!         let mut chunktps = ChunktpsConnection::new(tcp_stream);
 //!         thread::spawn(move || {
 //!             loop {
 //!                 let chunk = chunktps.read_chunk().unwrap();
@@ -30,6 +30,8 @@
 //!     }
 //! ```
 
+pub mod tls;
+
 use std::net::TcpStream;
 use std::error::Error;
 use std::fmt;
@@ -72,53 +74,60 @@ impl Display for ChunktpError {
 impl Error for ChunktpError {
 }
 
-/// A chunktp connection, now chunktps connection supports TCP only
-pub struct ChunktpConnection {
-    tcp_stream: TcpStream
+/// A chunktp connection, generic over any `Read + Write` byte stream (a plain `TcpStream` by
+/// default, or a TLS stream wrapping one - see `chunktps::tls`)
+pub struct ChunktpsConnection<S: Read + Write = TcpStream> {
+    stream: S
 }
 
-impl ChunktpConnection {
-    /// Creates a chunktp connection over a TCP stream. It does not make any assumption, check or
-    /// operation on the stream
-    pub fn new(tcp_stream: TcpStream) -> Self {
-        ChunktpConnection { tcp_stream }
+impl<S: Read + Write> ChunktpsConnection<S> {
+    /// Creates a chunktp connection over the given stream. It does not make any assumption, check
+    /// or operation on the stream
+    pub fn new(stream: S) -> Self {
+        ChunktpsConnection { stream }
     }
 
-    /// Try reading a chunk from the chunktp connection, returns Err type if the TCP stream fails
+    /// Try reading a chunk from the chunktp connection, returns Err type if the stream fails
     /// or the received buffer is ill-formed
     pub fn read_chunk(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
         let mut magic = [0u8; 4];
         let mut size = [0u8; 2];
 
-        self.tcp_stream.read_exact(&mut magic)?;
-        self.tcp_stream.read_exact(&mut size)?;
+        self.stream.read_exact(&mut magic)?;
+        self.stream.read_exact(&mut size)?;
         if magic != CHUNKTPS_MAGIC {
-            let _ = self.tcp_stream.write(&CHUNKTPS_READER_TE);
+            let _ = self.stream.write(&CHUNKTPS_READER_TE);
             return Err(Box::new(ChunktpError::new("incorrect chunktps magic!")));
         }
         let size = size[0] as usize * 256 + size[1] as usize;
 
         let mut recv_buffer = Vec::with_capacity(size);
         recv_buffer.resize_with(size, Default::default);
-        self.tcp_stream.read_exact(recv_buffer.as_mut_slice())?;
+        self.stream.read_exact(recv_buffer.as_mut_slice())?;
 
-        self.tcp_stream.write(&CHUNKTPS_READER_OK)?;
+        self.stream.write(&CHUNKTPS_READER_OK)?;
         Ok(recv_buffer)
     }
 
-    /// Try writing a chunk into the chunktp connection, returns Err type if the TCP stream fails
-    /// or the received buffer is ill-formed
+    /// Try writing a chunk into the chunktp connection, returns Err type if the stream fails,
+    /// the received buffer is ill-formed, or `data` is too large to fit in a single chunk (see
+    /// `CHUNK_MAX_SIZE`) -- callers that build chunks out of user-controlled data (e.g. an
+    /// arbitrary-length `Value`) must expect this rather than assume it always succeeds
     pub fn write_chunk(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
         let size = data.len();
-        assert!(size <= CHUNK_MAX_SIZE);
+        if size > CHUNK_MAX_SIZE {
+            return Err(Box::new(ChunktpError::new(&format!(
+                "chunk of {} bytes exceeds CHUNK_MAX_SIZE ({} bytes)", size, CHUNK_MAX_SIZE
+            ))));
+        }
         let size = [(size / 256) as u8, (size % 256) as u8];
 
-        self.tcp_stream.write(&CHUNKTPS_MAGIC)?;
-        self.tcp_stream.write(&size)?;
-        self.tcp_stream.write(data.as_slice())?;
+        self.stream.write(&CHUNKTPS_MAGIC)?;
+        self.stream.write(&size)?;
+        self.stream.write(data.as_slice())?;
 
         let mut client_reply = [0u8; 5];
-        self.tcp_stream.read_exact(&mut client_reply)?;
+        self.stream.read_exact(&mut client_reply)?;
 
         match client_reply {
             CHUNKTPS_READER_OK => Ok(()),
@@ -130,7 +139,7 @@ impl ChunktpConnection {
 
 #[cfg(test)]
 mod test {
-    use crate::chunktps::ChunktpConnection;
+    use crate::chunktps::ChunktpsConnection;
     use std::net::{TcpListener, TcpStream};
     use std::thread;
     use std::time::Duration;
@@ -153,7 +162,7 @@ mod test {
                 move || {
                     let listener = TcpListener::bind("127.0.0.1:8964").unwrap();
                     let (stream, _) = listener.accept().unwrap();
-                    let mut chunktps = ChunktpConnection::new(stream);
+                    let mut chunktps = ChunktpsConnection::new(stream);
                     for &piece in data.iter() {
                         chunktps.write_chunk(piece.to_vec()).unwrap();
                     }
@@ -162,7 +171,7 @@ mod test {
 
             thread::sleep(Duration::from_secs(1));
             let stream = TcpStream::connect("127.0.0.1:8964").unwrap();
-            let mut chunktps = ChunktpConnection::new(stream);
+            let mut chunktps = ChunktpsConnection::new(stream);
             for i in 0..data.len() {
                 assert_eq!(chunktps.read_chunk().unwrap(), data[i].to_vec());
             }
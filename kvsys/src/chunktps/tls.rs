@@ -0,0 +1,116 @@
+//! TLS support for chunktp connections, via `rustls`.
+//!
+//! `ChunktpsConnection` is generic over any `Read + Write` stream, so wrapping a `TcpStream` in a
+//! `rustls` session and handing the result to `ChunktpsConnection::new` is enough to run the exact
+//! same chunk framing over an encrypted socket. This module just provides the connect/accept
+//! helpers that build those sessions, the way Skytable offers an SSL port and an `sslonly` switch.
+
+use crate::chunktps::ChunktpsConnection;
+
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::BufReader;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+use rustls::{Certificate, ClientConfig, ClientConnection, PrivateKey, RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+use rustls::server::AllowAnyAuthenticatedClient;
+
+/// The error type used by the tls module
+#[derive(Debug)]
+pub struct TlsError {
+    description: String
+}
+
+impl TlsError {
+    pub fn new(description: &str) -> Self {
+        TlsError { description: description.to_owned() }
+    }
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "tls error: {}", self.description)
+    }
+}
+
+impl Error for TlsError {
+}
+
+/// A client-side TLS chunktp connection
+pub type ChunktpsTlsClientConnection = ChunktpsConnection<StreamOwned<ClientConnection, TcpStream>>;
+
+/// A server-side TLS chunktp connection
+pub type ChunktpsTlsServerConnection = ChunktpsConnection<StreamOwned<ServerConnection, TcpStream>>;
+
+/// Connects to `host` over TCP and negotiates TLS, trusting `cert` (a PEM file) as the root
+/// certificate, returning a `ChunktpsConnection` ready to speak chunktp framing over the
+/// encrypted stream
+pub fn connect(host: &str, cert: &Path) -> Result<ChunktpsTlsClientConnection, Box<dyn Error>> {
+    let mut roots = RootCertStore::empty();
+    let mut cert_reader = BufReader::new(fs::File::open(cert)?);
+    for der in rustls_pemfile::certs(&mut cert_reader)? {
+        roots.add(&Certificate(der))?;
+    }
+
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let server_name: rustls::ServerName = host.split(':').next().unwrap_or(host).try_into()
+        .map_err(|_| TlsError::new("invalid server name for TLS SNI"))?;
+    let connection = ClientConnection::new(Arc::new(config), server_name)?;
+    let tcp_stream = TcpStream::connect(host)?;
+    let stream = StreamOwned::new(connection, tcp_stream);
+    Ok(ChunktpsConnection::new(stream))
+}
+
+/// Accepts TLS connections on the server side, wrapping freshly-accepted `TcpStream`s
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>
+}
+
+impl TlsAcceptor {
+    /// Build an acceptor from a PEM certificate chain and a PKCS#8 private key on disk.
+    ///
+    /// If `client_ca_path` is given, the acceptor requires every connecting client to present a
+    /// certificate signed by one of the CAs in that PEM file (mutual TLS); otherwise any client
+    /// may connect once the handshake completes, as with a normal HTTPS-style server.
+    pub fn from_files(cert_path: &Path, key_path: &Path, client_ca_path: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let cert_chain = rustls_pemfile::certs(&mut BufReader::new(fs::File::open(cert_path)?))?
+            .into_iter().map(Certificate).collect();
+
+        let mut keys = rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(fs::File::open(key_path)?))?;
+        if keys.is_empty() {
+            return Err(Box::new(TlsError::new("no private key found in key file")));
+        }
+        let key = PrivateKey(keys.remove(0));
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let config = match client_ca_path {
+            Some(client_ca_path) => {
+                let mut client_roots = RootCertStore::empty();
+                let mut ca_reader = BufReader::new(fs::File::open(client_ca_path)?);
+                for der in rustls_pemfile::certs(&mut ca_reader)? {
+                    client_roots.add(&Certificate(der))?;
+                }
+                let verifier = AllowAnyAuthenticatedClient::new(client_roots);
+                builder.with_client_cert_verifier(verifier).with_single_cert(cert_chain, key)?
+            },
+            None => builder.with_no_client_auth().with_single_cert(cert_chain, key)?
+        };
+        Ok(TlsAcceptor { config: Arc::new(config) })
+    }
+
+    /// Performs the TLS handshake over an accepted `TcpStream`, producing a `ChunktpsConnection`
+    pub fn accept(&self, tcp_stream: TcpStream) -> Result<ChunktpsTlsServerConnection, Box<dyn Error>> {
+        let connection = ServerConnection::new(self.config.clone())?;
+        let stream = StreamOwned::new(connection, tcp_stream);
+        Ok(ChunktpsConnection::new(stream))
+    }
+}
@@ -2,10 +2,15 @@
 
 use std::fmt;
 use std::error::Error;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
 
-use crate::chunktps::ChunktpConnection;
+use crate::chunktps::ChunktpsConnection;
+use crate::chunktps::tls::{self, ChunktpsTlsClientConnection};
 use crate::kvstorage::{Key, Value};
-use crate::kvserver::protocol::{Request, ReplyChunk};
+use crate::kvserver::protocol::{ClientOpOutcome, ClientWatchEvent, Op, Request, ReplyChunk, ScanAccumulator};
+use crate::kvserver::resp;
+use crate::kvserver::resp::RespValue;
 use std::net::TcpStream;
 
 /// Error occurred on server, and received by client
@@ -29,20 +34,41 @@ impl ServerError {
     }
 }
 
-/// A key-value storage client, basically a wrapper for `ChunktpConnection`
+/// The wire transport a `KVClient` speaks to the server
+enum ClientTransport {
+    Chunktp(ChunktpsConnection<TcpStream>),
+    ChunktpTls(ChunktpsTlsClientConnection),
+    Resp(BufReader<TcpStream>)
+}
+
+/// A key-value storage client, wrapping a `ChunktpsConnection` (plaintext or TLS) or a RESP
+/// connection
 ///
 /// `KVClient` relies on callback functions to handle server returned results since server can
 /// send reply in multi-chunk form, while caching all these chunks is somewhat expensive. If
 /// there's an error when reading and parsing server reply, the callback function will not be
 /// called. Read documentation of `do_xx` functions for further information
 pub struct KVClient {
-    chunktps: ChunktpConnection
+    transport: ClientTransport
 }
 
 impl KVClient {
-    /// Creates a `KVClient` using the given `TcpStream`
+    /// Creates a `KVClient` using the given `TcpStream`, speaking chunktp
     pub fn new(tcp_stream: TcpStream) -> Self {
-        KVClient { chunktps: ChunktpConnection::new(tcp_stream) }
+        KVClient { transport: ClientTransport::Chunktp(ChunktpsConnection::new(tcp_stream)) }
+    }
+
+    /// Creates a `KVClient` that connects to `host` over TLS, trusting `cert` as the root
+    /// certificate, and speaks chunktp framing over the encrypted stream
+    pub fn new_tls(host: &str, cert: &Path) -> Result<Self, Box<dyn Error>> {
+        let connection = tls::connect(host, cert)?;
+        Ok(KVClient { transport: ClientTransport::ChunktpTls(connection) })
+    }
+
+    /// Creates a `KVClient` that speaks RESP (the redis protocol, see `kvserver::resp`) instead of
+    /// chunktp, so it can talk to the RESP front-end or to a real redis-compatible server
+    pub fn new_resp(tcp_stream: TcpStream) -> Self {
+        KVClient { transport: ClientTransport::Resp(BufReader::new(tcp_stream)) }
     }
 
     /// Trying get a value corresponding to the given `Key`
@@ -65,13 +91,36 @@ impl KVClient {
     /// Returns `Err` if TCP connection fails or Chunktp fails
     pub fn do_get<F, T>(&mut self, key: Key, result_handler: F) -> Result<T, Box<dyn Error>>
         where F: Fn(Option<Value>) -> T {
-        self.chunktps.write_chunk(Request::Get(key).serialize())?;
-        let reply = ReplyChunk::deserialize(self.chunktps.read_chunk()?)?;
-        match reply {
-            ReplyChunk::SingleValue(value ) => {
-                Ok(result_handler(value))
+        match &mut self.transport {
+            ClientTransport::Chunktp(chunktps) => {
+                chunktps.write_chunk(Request::Get(key).serialize())?;
+                let reply = ReplyChunk::deserialize(chunktps.read_chunk()?)?;
+                match reply {
+                    ReplyChunk::SingleValue(value) => Ok(result_handler(value)),
+                    _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+            },
+            ClientTransport::ChunktpTls(chunktps) => {
+                chunktps.write_chunk(Request::Get(key).serialize())?;
+                let reply = ReplyChunk::deserialize(chunktps.read_chunk()?)?;
+                match reply {
+                    ReplyChunk::SingleValue(value) => Ok(result_handler(value)),
+                    _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+            },
+            ClientTransport::Resp(stream) => {
+                stream.get_mut().write_all(&resp::encode_command(&[b"GET", &key.serialize()]))?;
+                match resp::read_value(stream)? {
+                    RespValue::Bulk(Some(raw)) => {
+                        let value = Value::from_slice_checked(&raw)
+                            .ok_or_else(|| ServerError::new("incorrect value size in RESP reply"))?;
+                        Ok(result_handler(Some(value)))
+                    },
+                    RespValue::Bulk(None) => Ok(result_handler(None)),
+                    RespValue::Error(msg) => Err(Box::new(ServerError::new(&msg))),
+                    _ => Err(Box::new(ServerError::new("unexpected RESP reply kind")))
+                }
             }
-            _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
         }
     }
 
@@ -82,16 +131,33 @@ impl KVClient {
     ///
     /// Returns `Err` if TCP connection fails, Chunktp fails or server fails.
     pub fn do_put(&mut self, key: Key, value: Value) -> Result<(), Box<dyn Error>> {
-        self.chunktps.write_chunk(Request::Put(key, value).serialize())?;
-        let reply = ReplyChunk::deserialize(self.chunktps.read_chunk()?)?;
-        match reply {
-            ReplyChunk::Success => {
-                Ok(())
-            },
-            ReplyChunk::Error => {
-                Err(Box::new(ServerError::new("error inserting kv pair")))
+        match &mut self.transport {
+            ClientTransport::Chunktp(chunktps) => {
+                chunktps.write_chunk(Request::Put(key, value).serialize())?;
+                let reply = ReplyChunk::deserialize(chunktps.read_chunk()?)?;
+                match reply {
+                    ReplyChunk::Success => Ok(()),
+                    ReplyChunk::Error => Err(Box::new(ServerError::new("error inserting kv pair"))),
+                    _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+            },
+            ClientTransport::ChunktpTls(chunktps) => {
+                chunktps.write_chunk(Request::Put(key, value).serialize())?;
+                let reply = ReplyChunk::deserialize(chunktps.read_chunk()?)?;
+                match reply {
+                    ReplyChunk::Success => Ok(()),
+                    ReplyChunk::Error => Err(Box::new(ServerError::new("error inserting kv pair"))),
+                    _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+            },
+            ClientTransport::Resp(stream) => {
+                stream.get_mut().write_all(&resp::encode_command(&[b"SET", &key.serialize(), &value.serialize()]))?;
+                match resp::read_value(stream)? {
+                    RespValue::Simple(_) => Ok(()),
+                    RespValue::Error(msg) => Err(Box::new(ServerError::new(&msg))),
+                    _ => Err(Box::new(ServerError::new("unexpected RESP reply kind")))
+                }
             }
-            _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
         }
     }
 
@@ -114,23 +180,151 @@ impl KVClient {
     /// Returns `Err` if TCP connection fails or Chunktp fails
     pub fn do_scan<F, T>(&mut self, key1: Key, key2: Key, chunk_handler: F) -> Result<Vec<T>, Box<dyn Error>>
         where F: Fn(Vec<(Key, Value)>) -> T {
-        self.chunktps.write_chunk(Request::Scan(key1, key2).serialize())?;
-        let mut ret = Vec::new();
-        loop {
-            let chunk = self.chunktps.read_chunk()?;
-            if chunk.len() == 0 {
-                return Ok(ret)
-            }
-            let reply = ReplyChunk::deserialize(chunk)?;
-            match reply {
-                ReplyChunk::KVPairs(kv_pairs) => {
-                    ret.push(chunk_handler(kv_pairs));
-                },
-                _ => return Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+        match &mut self.transport {
+            ClientTransport::Chunktp(chunktps) => {
+                chunktps.write_chunk(Request::Scan(key1, key2).serialize())?;
+                match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                    ReplyChunk::ScanStarted(_) => (),
+                    _ => return Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+                let mut ret = Vec::new();
+                loop {
+                    match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                        ReplyChunk::KVPairs(kv_pairs) => ret.push(chunk_handler(kv_pairs)),
+                        ReplyChunk::ScanEnd => return Ok(ret),
+                        _ => return Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                    }
+                }
+            },
+            ClientTransport::ChunktpTls(chunktps) => {
+                chunktps.write_chunk(Request::Scan(key1, key2).serialize())?;
+                match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                    ReplyChunk::ScanStarted(_) => (),
+                    _ => return Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+                let mut ret = Vec::new();
+                loop {
+                    match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                        ReplyChunk::KVPairs(kv_pairs) => ret.push(chunk_handler(kv_pairs)),
+                        ReplyChunk::ScanEnd => return Ok(ret),
+                        _ => return Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                    }
+                }
+            },
+            ClientTransport::Resp(stream) => {
+                stream.get_mut().write_all(
+                    &resp::encode_command(&[b"SCAN", &key1.serialize(), &key2.serialize()]))?;
+                match resp::read_value(stream)? {
+                    RespValue::Array(Some(items)) => {
+                        let mut kv_pairs = Vec::with_capacity(items.len());
+                        for item in items {
+                            match item {
+                                RespValue::Array(Some(mut pair)) if pair.len() == 2 => {
+                                    let raw_value = pair.pop().unwrap();
+                                    let raw_key = pair.pop().unwrap();
+                                    let (raw_key, raw_value) = match (raw_key, raw_value) {
+                                        (RespValue::Bulk(Some(k)), RespValue::Bulk(Some(v))) => (k, v),
+                                        _ => return Err(Box::new(ServerError::new("malformed scan pair in RESP reply")))
+                                    };
+                                    let key = Key::from_slice_checked(&raw_key)
+                                        .ok_or_else(|| ServerError::new("incorrect key size in RESP reply"))?;
+                                    let value = Value::from_slice_checked(&raw_value)
+                                        .ok_or_else(|| ServerError::new("incorrect value size in RESP reply"))?;
+                                    kv_pairs.push((key, value));
+                                },
+                                _ => return Err(Box::new(ServerError::new("malformed scan pair in RESP reply")))
+                            }
+                        }
+                        Ok(vec![chunk_handler(kv_pairs)])
+                    },
+                    RespValue::Error(msg) => Err(Box::new(ServerError::new(&msg))),
+                    _ => Err(Box::new(ServerError::new("unexpected RESP reply kind")))
+                }
             }
         }
     }
 
+    /// Like `do_scan`, but also returns the `scan_id` the server assigned the stream, so a caller
+    /// that loses the connection mid-scan can pick up where it left off with `do_resume` instead of
+    /// restarting the whole range.
+    ///
+    /// Only supported over chunktp (plaintext or TLS); returns `Err` over a RESP connection, since
+    /// RESP has no resumable-scan command.
+    pub fn do_scan_resumable<F, T>(&mut self, key1: Key, key2: Key, chunk_handler: F) -> Result<(u64, Vec<T>), Box<dyn Error>>
+        where F: Fn(Vec<(Key, Value)>) -> T {
+        match &mut self.transport {
+            ClientTransport::Chunktp(chunktps) => {
+                chunktps.write_chunk(Request::Scan(key1, key2).serialize())?;
+                let scan_id = match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                    ReplyChunk::ScanStarted(scan_id) => scan_id,
+                    _ => return Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                };
+                let mut ret = Vec::new();
+                loop {
+                    match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                        ReplyChunk::KVPairs(kv_pairs) => ret.push(chunk_handler(kv_pairs)),
+                        ReplyChunk::ScanEnd => return Ok((scan_id, ret)),
+                        _ => return Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                    }
+                }
+            },
+            ClientTransport::ChunktpTls(chunktps) => {
+                chunktps.write_chunk(Request::Scan(key1, key2).serialize())?;
+                let scan_id = match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                    ReplyChunk::ScanStarted(scan_id) => scan_id,
+                    _ => return Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                };
+                let mut ret = Vec::new();
+                loop {
+                    match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                        ReplyChunk::KVPairs(kv_pairs) => ret.push(chunk_handler(kv_pairs)),
+                        ReplyChunk::ScanEnd => return Ok((scan_id, ret)),
+                        _ => return Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                    }
+                }
+            },
+            ClientTransport::Resp(_) => Err(Box::new(ServerError::new("resumable scan requests are not supported over RESP")))
+        }
+    }
+
+    /// Re-opens the `scan_id` stream from a previous `do_scan_resumable` call (or an earlier
+    /// `do_resume` call), resuming strictly after `last_key` instead of re-sending already-received
+    /// pairs. Fails if `scan_id` is unknown to the server or has expired -- see
+    /// `kvserver::resync::ScanResumeRegistry`.
+    ///
+    /// Only supported over chunktp (plaintext or TLS); returns `Err` over a RESP connection, since
+    /// RESP has no resume command.
+    pub fn do_resume<F, T>(&mut self, scan_id: u64, last_key: Key, chunk_handler: F) -> Result<Vec<T>, Box<dyn Error>>
+        where F: Fn(Vec<(Key, Value)>) -> T {
+        match &mut self.transport {
+            ClientTransport::Chunktp(chunktps) => {
+                chunktps.write_chunk(Request::Resume(scan_id, last_key).serialize())?;
+                let mut ret = Vec::new();
+                loop {
+                    match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                        ReplyChunk::KVPairs(kv_pairs) => ret.push(chunk_handler(kv_pairs)),
+                        ReplyChunk::ScanEnd => return Ok(ret),
+                        ReplyChunk::Error => return Err(Box::new(ServerError::new("scan_id is unknown or has expired"))),
+                        _ => return Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                    }
+                }
+            },
+            ClientTransport::ChunktpTls(chunktps) => {
+                chunktps.write_chunk(Request::Resume(scan_id, last_key).serialize())?;
+                let mut ret = Vec::new();
+                loop {
+                    match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                        ReplyChunk::KVPairs(kv_pairs) => ret.push(chunk_handler(kv_pairs)),
+                        ReplyChunk::ScanEnd => return Ok(ret),
+                        ReplyChunk::Error => return Err(Box::new(ServerError::new("scan_id is unknown or has expired"))),
+                        _ => return Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                    }
+                }
+            },
+            ClientTransport::Resp(_) => Err(Box::new(ServerError::new("resume requests are not supported over RESP")))
+        }
+    }
+
     /// Trying delete the `key` from storage
     ///
     /// The result handler function should accept a `usize`, rows affected by the delete operation
@@ -145,20 +339,334 @@ impl KVClient {
     /// Returns `Err` if TCP connection fails, Chunktp fails or server fails
     pub fn do_delete<F, T>(&mut self, key: Key, result_handler: F) -> Result<T, Box<dyn Error>>
         where F: Fn(usize) -> T {
-        self.chunktps.write_chunk(Request::Del(key).serialize())?;
-        let reply = ReplyChunk::deserialize(self.chunktps.read_chunk()?)?;
-        match reply {
-            ReplyChunk::Number(number ) => {
-                Ok(result_handler(number))
-            },
-            ReplyChunk::Error => {
-                Err(Box::new(ServerError::new("error deleting kv pair")))
+        match &mut self.transport {
+            ClientTransport::Chunktp(chunktps) => {
+                chunktps.write_chunk(Request::Del(key).serialize())?;
+                let reply = ReplyChunk::deserialize(chunktps.read_chunk()?)?;
+                match reply {
+                    ReplyChunk::Number(number) => Ok(result_handler(number)),
+                    ReplyChunk::Error => Err(Box::new(ServerError::new("error deleting kv pair"))),
+                    _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+            },
+            ClientTransport::ChunktpTls(chunktps) => {
+                chunktps.write_chunk(Request::Del(key).serialize())?;
+                let reply = ReplyChunk::deserialize(chunktps.read_chunk()?)?;
+                match reply {
+                    ReplyChunk::Number(number) => Ok(result_handler(number)),
+                    ReplyChunk::Error => Err(Box::new(ServerError::new("error deleting kv pair"))),
+                    _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+            },
+            ClientTransport::Resp(stream) => {
+                stream.get_mut().write_all(&resp::encode_command(&[b"DEL", &key.serialize()]))?;
+                match resp::read_value(stream)? {
+                    RespValue::Integer(n) => Ok(result_handler(n as usize)),
+                    RespValue::Error(msg) => Err(Box::new(ServerError::new(&msg))),
+                    _ => Err(Box::new(ServerError::new("unexpected RESP reply kind")))
+                }
             }
-            _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+        }
+    }
+
+    /// Sends `ops` as a single `Request::Batch`, applied by the server under one write lock so the
+    /// whole batch is atomic relative to every other connection's writes -- unlike `do_batch`,
+    /// which pipelines independent requests with no such guarantee between them.
+    ///
+    /// Returns one `ClientOpOutcome` per op, in the same order as `ops`.
+    ///
+    /// Only supported over chunktp (plaintext or TLS); returns `Err` over a RESP connection, since
+    /// RESP has no batch command.
+    pub fn do_atomic_batch(&mut self, ops: Vec<Op>) -> Result<Vec<ClientOpOutcome>, Box<dyn Error>> {
+        match &mut self.transport {
+            ClientTransport::Chunktp(chunktps) => {
+                chunktps.write_chunk(Request::Batch(ops).serialize())?;
+                match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                    ReplyChunk::BatchResult(outcomes) => Ok(outcomes),
+                    _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+            },
+            ClientTransport::ChunktpTls(chunktps) => {
+                chunktps.write_chunk(Request::Batch(ops).serialize())?;
+                match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                    ReplyChunk::BatchResult(outcomes) => Ok(outcomes),
+                    _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+            },
+            ClientTransport::Resp(_) => Err(Box::new(ServerError::new("batch requests are not supported over RESP")))
+        }
+    }
+
+    /// Blocks until `key` is PUT or DEL'd, or `timeout_ms` elapses, returning the observed change
+    /// (or `None` on timeout). Lets a caller maintain a change-driven cache instead of polling
+    /// with repeated `do_get` calls.
+    ///
+    /// Only supported over chunktp (plaintext or TLS); returns `Err` over a RESP connection, since
+    /// RESP has no watch command.
+    pub fn do_watch(&mut self, key: Key, timeout_ms: u64) -> Result<Option<ClientWatchEvent>, Box<dyn Error>> {
+        self.do_watch_request(Request::Watch(key, timeout_ms))
+    }
+
+    /// Like `do_watch`, but matches any key in `[key1, key2)` instead of a single key
+    pub fn do_watch_range(&mut self, key1: Key, key2: Key, timeout_ms: u64) -> Result<Option<ClientWatchEvent>, Box<dyn Error>> {
+        self.do_watch_request(Request::WatchRange(key1, key2, timeout_ms))
+    }
+
+    /// Fetches at most `limit` pairs within `[key1, key2)`, resuming strictly after `after_token`
+    /// (or starting at `key1` if `None`). Returns the page of pairs and the continuation token to
+    /// pass as `after_token` on the next call, or `None` once the range is exhausted -- so a
+    /// caller can page through a huge keyspace in bounded-size requests, holding the server's read
+    /// lock for only one page at a time, and resume exactly where it left off after a disconnect.
+    ///
+    /// Only supported over chunktp (plaintext or TLS); returns `Err` over a RESP connection, since
+    /// RESP has no paginated scan command.
+    pub fn do_scan_page(&mut self, key1: Key, key2: Key, limit: usize, after_token: Option<Key>)
+        -> Result<(Vec<(Key, Value)>, Option<Key>), Box<dyn Error>> {
+        let request = Request::ScanPage(key1, key2, limit, after_token);
+        match &mut self.transport {
+            ClientTransport::Chunktp(chunktps) => {
+                chunktps.write_chunk(request.serialize())?;
+                match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                    ReplyChunk::Page(pairs, next_token) => Ok((pairs, next_token)),
+                    _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+            },
+            ClientTransport::ChunktpTls(chunktps) => {
+                chunktps.write_chunk(request.serialize())?;
+                match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                    ReplyChunk::Page(pairs, next_token) => Ok((pairs, next_token)),
+                    _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+            },
+            ClientTransport::Resp(_) => Err(Box::new(ServerError::new("paginated scan requests are not supported over RESP")))
+        }
+    }
+
+    fn do_watch_request(&mut self, request: Request) -> Result<Option<ClientWatchEvent>, Box<dyn Error>> {
+        match &mut self.transport {
+            ClientTransport::Chunktp(chunktps) => {
+                chunktps.write_chunk(request.serialize())?;
+                match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                    ReplyChunk::Watch(event) => Ok(event),
+                    _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+            },
+            ClientTransport::ChunktpTls(chunktps) => {
+                chunktps.write_chunk(request.serialize())?;
+                match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                    ReplyChunk::Watch(event) => Ok(event),
+                    _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+                }
+            },
+            ClientTransport::Resp(_) => Err(Box::new(ServerError::new("watch requests are not supported over RESP")))
+        }
+    }
+
+    /// Sends every `Request` in `requests` back-to-back before reading any reply, then reads the
+    /// replies back in the same order and correlates each to its request — the round-trip-saving
+    /// pipelining technique RESP-speaking clients like redis rely on, rather than the
+    /// one-write-then-one-read-per-call that every other `do_xx` method performs.
+    ///
+    /// A `Request::Scan` consumes reply chunks until the `ScanEnd` terminator, exactly
+    /// like `do_scan`; every other request consumes exactly one reply (`Request::Close` consumes
+    /// none, since neither chunktp nor RESP servers reply to it). A request whose reply the server
+    /// reports as an error, or whose reply doesn't match its request kind, becomes an `Err` at its
+    /// own position in the returned `Vec` without aborting the rest of the batch.
+    pub fn do_batch(&mut self, requests: Vec<Request>) -> Vec<Result<BatchReply, Box<dyn Error>>> {
+        match &mut self.transport {
+            ClientTransport::Chunktp(chunktps) => batch_over_chunktp(chunktps, &requests),
+            ClientTransport::ChunktpTls(chunktps) => batch_over_chunktp(chunktps, &requests),
+            ClientTransport::Resp(stream) => batch_over_resp(stream, &requests)
         }
     }
 
     pub fn do_close(&mut self) {
-        let _ = self.chunktps.write_chunk(Request::Close.serialize());
+        match &mut self.transport {
+            ClientTransport::Chunktp(chunktps) => {
+                let _ = chunktps.write_chunk(Request::Close.serialize());
+            },
+            ClientTransport::ChunktpTls(chunktps) => {
+                let _ = chunktps.write_chunk(Request::Close.serialize());
+            },
+            ClientTransport::Resp(stream) => {
+                let _ = stream.get_mut().write_all(&resp::encode_command(&[b"QUIT"]));
+            }
+        }
+    }
+}
+
+/// One request's outcome out of a `KVClient::do_batch` call. Mirrors `ReplyChunk`, except `Scan`
+/// accumulates every chunk up to (not including) the `ScanEnd` terminator into a single
+/// `Vec`, the same shape `do_scan`'s RESP arm already returns to its caller.
+pub enum BatchReply {
+    Value(Option<Value>),
+    Number(usize),
+    KvPairs(Vec<(Key, Value)>),
+    /// The per-op outcomes of a pipelined `Request::Batch`, see `ClientOpOutcome`
+    BatchResult(Vec<ClientOpOutcome>),
+    /// The reply to a pipelined `Request::Watch`/`WatchRange`, see `ClientWatchEvent`
+    Watch(Option<ClientWatchEvent>),
+    /// The reply to a pipelined `Request::ScanPage`, see `KVClient::do_scan_page`
+    Page(Vec<(Key, Value)>, Option<Key>),
+    Success
+}
+
+fn batch_over_chunktp<S: Read + Write>(chunktps: &mut ChunktpsConnection<S>, requests: &[Request]) -> Vec<Result<BatchReply, Box<dyn Error>>> {
+    for request in requests {
+        if let Err(e) = chunktps.write_chunk(request.serialize()) {
+            return requests.iter().map(|_| Err(Box::new(ServerError::new(&format!("failed writing pipelined request: {}", e))) as Box<dyn Error>)).collect();
+        }
+    }
+
+    requests.iter().map(|request| chunktp_reply_for(chunktps, request)).collect()
+}
+
+fn chunktp_reply_for<S: Read + Write>(chunktps: &mut ChunktpsConnection<S>, request: &Request) -> Result<BatchReply, Box<dyn Error>> {
+    match request {
+        Request::Get(_) => {
+            match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                ReplyChunk::SingleValue(value) => Ok(BatchReply::Value(value)),
+                _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+            }
+        },
+        Request::Put(_, _) => {
+            match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                ReplyChunk::Success => Ok(BatchReply::Success),
+                ReplyChunk::Error => Err(Box::new(ServerError::new("error inserting kv pair"))),
+                _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+            }
+        },
+        Request::Del(_) => {
+            match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                ReplyChunk::Number(number) => Ok(BatchReply::Number(number)),
+                ReplyChunk::Error => Err(Box::new(ServerError::new("error deleting kv pair"))),
+                _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+            }
+        },
+        Request::Scan(_, _) => {
+            match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                ReplyChunk::ScanStarted(_) => (),
+                _ => return Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+            }
+            let mut acc = ScanAccumulator::new();
+            loop {
+                if acc.push(chunktps.read_chunk()?)? {
+                    return Ok(BatchReply::KvPairs(acc.into_pairs()));
+                }
+            }
+        },
+        Request::Resume(_, _) => {
+            let mut acc = ScanAccumulator::new();
+            loop {
+                if acc.push(chunktps.read_chunk()?)? {
+                    return Ok(BatchReply::KvPairs(acc.into_pairs()));
+                }
+            }
+        },
+        Request::Batch(_) => {
+            match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                ReplyChunk::BatchResult(outcomes) => Ok(BatchReply::BatchResult(outcomes)),
+                _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+            }
+        },
+        Request::Watch(_, _) | Request::WatchRange(_, _, _) => {
+            match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                ReplyChunk::Watch(event) => Ok(BatchReply::Watch(event)),
+                _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+            }
+        },
+        Request::ScanPage(_, _, _, _) => {
+            match ReplyChunk::deserialize(chunktps.read_chunk()?)? {
+                ReplyChunk::Page(pairs, next_token) => Ok(BatchReply::Page(pairs, next_token)),
+                _ => Err(Box::new(ServerError::new("unexpected reply chunk kind")))
+            }
+        },
+        Request::Close => Ok(BatchReply::Success)
+    }
+}
+
+fn batch_over_resp(stream: &mut BufReader<TcpStream>, requests: &[Request]) -> Vec<Result<BatchReply, Box<dyn Error>>> {
+    for request in requests {
+        let command = match request {
+            Request::Get(key) => resp::encode_command(&[b"GET", &key.serialize()]),
+            Request::Put(key, value) => resp::encode_command(&[b"SET", &key.serialize(), &value.serialize()]),
+            Request::Del(key) => resp::encode_command(&[b"DEL", &key.serialize()]),
+            Request::Scan(key1, key2) => resp::encode_command(&[b"SCAN", &key1.serialize(), &key2.serialize()]),
+            // RESP has no batch, watch, paginated-scan or resume command; the server's RESP
+            // front-end rejects these as unsupported commands, which surfaces to the caller as
+            // `RespValue::Error` below
+            Request::Batch(_) => resp::encode_command(&[b"BATCH"]),
+            Request::Watch(_, _) | Request::WatchRange(_, _, _) => resp::encode_command(&[b"WATCH"]),
+            Request::ScanPage(_, _, _, _) => resp::encode_command(&[b"SCANPAGE"]),
+            Request::Resume(_, _) => resp::encode_command(&[b"RESUME"]),
+            Request::Close => resp::encode_command(&[b"QUIT"])
+        };
+        if let Err(e) = stream.get_mut().write_all(&command) {
+            return requests.iter().map(|_| Err(Box::new(ServerError::new(&format!("failed writing pipelined request: {}", e))) as Box<dyn Error>)).collect();
+        }
+    }
+
+    requests.iter().map(|request| resp_reply_for(stream, request)).collect()
+}
+
+fn resp_reply_for(stream: &mut BufReader<TcpStream>, request: &Request) -> Result<BatchReply, Box<dyn Error>> {
+    if let Request::Close = request {
+        return Ok(BatchReply::Success);
+    }
+
+    match resp::read_value(stream)? {
+        RespValue::Error(msg) => Err(Box::new(ServerError::new(&msg))),
+        value => match request {
+            Request::Get(_) => match value {
+                RespValue::Bulk(Some(raw)) => {
+                    let value = Value::from_slice_checked(&raw)
+                        .ok_or_else(|| ServerError::new("incorrect value size in RESP reply"))?;
+                    Ok(BatchReply::Value(Some(value)))
+                },
+                RespValue::Bulk(None) => Ok(BatchReply::Value(None)),
+                _ => Err(Box::new(ServerError::new("unexpected RESP reply kind")))
+            },
+            Request::Put(_, _) => match value {
+                RespValue::Simple(_) => Ok(BatchReply::Success),
+                _ => Err(Box::new(ServerError::new("unexpected RESP reply kind")))
+            },
+            Request::Del(_) => match value {
+                RespValue::Integer(n) => Ok(BatchReply::Number(n as usize)),
+                _ => Err(Box::new(ServerError::new("unexpected RESP reply kind")))
+            },
+            Request::Scan(_, _) => match value {
+                RespValue::Array(Some(items)) => {
+                    let mut kv_pairs = Vec::with_capacity(items.len());
+                    for item in items {
+                        match item {
+                            RespValue::Array(Some(mut pair)) if pair.len() == 2 => {
+                                let raw_value = pair.pop().unwrap();
+                                let raw_key = pair.pop().unwrap();
+                                let (raw_key, raw_value) = match (raw_key, raw_value) {
+                                    (RespValue::Bulk(Some(k)), RespValue::Bulk(Some(v))) => (k, v),
+                                    _ => return Err(Box::new(ServerError::new("malformed scan pair in RESP reply")))
+                                };
+                                let key = Key::from_slice_checked(&raw_key)
+                                    .ok_or_else(|| ServerError::new("incorrect key size in RESP reply"))?;
+                                let value = Value::from_slice_checked(&raw_value)
+                                    .ok_or_else(|| ServerError::new("incorrect value size in RESP reply"))?;
+                                kv_pairs.push((key, value));
+                            },
+                            _ => return Err(Box::new(ServerError::new("malformed scan pair in RESP reply")))
+                        }
+                    }
+                    Ok(BatchReply::KvPairs(kv_pairs))
+                },
+                _ => Err(Box::new(ServerError::new("unexpected RESP reply kind")))
+            },
+            Request::Batch(_) => Err(Box::new(ServerError::new("batch requests are not supported over RESP"))),
+            Request::Watch(_, _) | Request::WatchRange(_, _, _) =>
+                Err(Box::new(ServerError::new("watch requests are not supported over RESP"))),
+            Request::ScanPage(_, _, _, _) =>
+                Err(Box::new(ServerError::new("paginated scan requests are not supported over RESP"))),
+            Request::Resume(_, _) =>
+                Err(Box::new(ServerError::new("resume requests are not supported over RESP"))),
+            Request::Close => unreachable!("handled above")
+        }
     }
 }
@@ -0,0 +1,56 @@
+//! Bounds the number of connections queued for or being handled at once, so a connection flood
+//! can't grow the thread pool's pending work without limit.
+//!
+//! `run_server` builds one `ConnectionLimiter` (sized by `max_in_flight_connections`) and calls
+//! `acquire` from the accept loop, right before dispatching each connection to the thread pool.
+//! `acquire` blocks the accept loop itself once `max_in_flight` connections are already queued or
+//! being handled, so backpressure lands on the TCP accept queue instead of unbounded memory. The
+//! returned `ConnectionPermit` is moved into the connection's closure and releases the slot (via
+//! `Drop`) once `handle_connection` returns.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::kvserver::metrics::ServerMetrics;
+
+/// Caps the number of connections queued or being handled at once
+pub struct ConnectionLimiter {
+    max_in_flight: u32,
+    in_flight: Mutex<u32>,
+    slot_freed: Condvar
+}
+
+impl ConnectionLimiter {
+    /// Creates a limiter allowing up to `max_in_flight` connections to be queued or handled at once
+    pub fn new(max_in_flight: u32) -> Self {
+        ConnectionLimiter { max_in_flight, in_flight: Mutex::new(0), slot_freed: Condvar::new() }
+    }
+
+    /// Blocks the calling (accept loop) thread until a slot is free, then reserves it and reports
+    /// the new queue depth to `metrics`. The slot is released, and the gauge updated again, when
+    /// the returned `ConnectionPermit` is dropped.
+    pub fn acquire(self: &Arc<Self>, metrics: &Arc<ServerMetrics>) -> ConnectionPermit {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        while *in_flight >= self.max_in_flight {
+            in_flight = self.slot_freed.wait(in_flight).unwrap();
+        }
+        *in_flight += 1;
+        metrics.set_in_flight_connections(*in_flight);
+
+        ConnectionPermit { limiter: self.clone(), metrics: metrics.clone() }
+    }
+}
+
+/// RAII handle on a slot reserved by `ConnectionLimiter::acquire`; releases the slot on drop
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    metrics: Arc<ServerMetrics>
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut in_flight = self.limiter.in_flight.lock().unwrap();
+        *in_flight -= 1;
+        self.metrics.set_in_flight_connections(*in_flight);
+        self.limiter.slot_freed.notify_one();
+    }
+}
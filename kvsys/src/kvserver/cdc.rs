@@ -0,0 +1,138 @@
+//! Change-data-capture sink: streams every successful `Request::Put`/`Request::Del` mutation to
+//! an external Kafka topic, so downstream consumers (search indexers, replicas, analytics) see a
+//! live feed of every write without polling the KV store.
+//!
+//! `run_server` builds one `CdcSink` (if `cdc_kafka_brokers`/`cdc_kafka_topic` are configured) and
+//! shares it into every connection. `handle_connection`'s `Request::Put`/`Request::Del` arms (and
+//! `Request::Batch`'s `Op::Put`/`Op::Del` arms) call `publish` after the write has landed in
+//! `storage_engine`. `publish` hands the record to a bounded channel drained by a background
+//! thread that owns the Kafka producer, so a slow or unreachable broker never stalls request
+//! handling; whether a full buffer drops the record or blocks the caller is controlled by
+//! `drop_on_overflow`.
+
+use std::fmt;
+use std::error::Error;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+use log::{error, warn};
+use rdkafka::config::ClientConfig;
+use rdkafka::producer::{BaseProducer, BaseRecord, Producer};
+
+use crate::kvstorage::{Key, Serializable, Value};
+
+/// The error type used by the cdc module
+#[derive(Debug)]
+pub struct CdcError {
+    description: String
+}
+
+impl CdcError {
+    pub fn new(description: &str) -> Self {
+        CdcError { description: description.to_owned() }
+    }
+}
+
+impl fmt::Display for CdcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(f, "cdc error: {}", self.description)
+    }
+}
+
+impl Error for CdcError {
+}
+
+/// The mutation a `CdcRecord` describes
+pub enum CdcOp {
+    Put,
+    Del
+}
+
+/// One mutation, in the order `CdcSink` observed it. Serialized as the Kafka message payload:
+/// 1 byte op ('P' or 'D'), KEY_SIZE key, 1 byte value presence, then -- only if present -- the
+/// value via its `Serializable` impl (a VarInt byte-length prefix followed by that many raw
+/// bytes, see `kvstorage::Value`), finally 8 bytes seq
+pub struct CdcRecord {
+    pub op: CdcOp,
+    pub key: Key,
+    pub value: Option<Value>,
+    pub seq: u64
+}
+
+impl Serializable for CdcRecord {
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        buf.push(match self.op {
+            CdcOp::Put => b'P',
+            CdcOp::Del => b'D'
+        });
+        self.key.write_to(buf);
+        match &self.value {
+            Some(value) => {
+                buf.push(1);
+                value.write_to(buf);
+            },
+            None => buf.push(0)
+        }
+        buf.extend_from_slice(&self.seq.to_be_bytes());
+    }
+}
+
+/// Streams CDC records to Kafka from a background thread, decoupled from request handling by a
+/// bounded channel
+pub struct CdcSink {
+    sender: SyncSender<CdcRecord>,
+    next_seq: AtomicU64,
+    drop_on_overflow: bool
+}
+
+impl CdcSink {
+    /// Connects to `brokers` (a comma-separated Kafka bootstrap server list) and spawns the
+    /// background thread that publishes to `topic`, buffering up to `buffer_size` records in
+    /// flight. Returns `Err` if the producer can't be constructed.
+    pub fn new(brokers: &str, topic: &str, buffer_size: usize, drop_on_overflow: bool) -> Result<Self, CdcError> {
+        let producer: BaseProducer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .create()
+            .map_err(|e| CdcError::new(&format!("failed constructing Kafka producer for '{}': {}", brokers, e)))?;
+
+        let (sender, receiver) = mpsc::sync_channel(buffer_size);
+        let topic = topic.to_owned();
+        thread::spawn(move || run_producer_loop(producer, topic, receiver));
+
+        Ok(CdcSink { sender, next_seq: AtomicU64::new(0), drop_on_overflow })
+    }
+
+    /// Publishes a mutation, assigning it the next sequence number. If the buffer is full, drops
+    /// the record (logging a warning) when `drop_on_overflow` is set; otherwise blocks the calling
+    /// (request-handling) thread until the background thread drains room for it.
+    pub fn publish(&self, op: CdcOp, key: Key, value: Option<Value>) {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let record = CdcRecord { op, key, value, seq };
+
+        if self.drop_on_overflow {
+            if let Err(TrySendError::Full(_)) = self.sender.try_send(record) {
+                warn!("cdc buffer is full, dropping mutation (seq {})", seq);
+            }
+        } else {
+            // only fails if the producer thread has exited (e.g. panicked), in which case there's
+            // nothing useful left to do with the record
+            let _ = self.sender.send(record);
+        }
+    }
+}
+
+fn run_producer_loop(producer: BaseProducer, topic: String, receiver: Receiver<CdcRecord>) {
+    for record in receiver {
+        let seq = record.seq;
+        let payload = record.serialize();
+        let key_bytes = record.key.serialize();
+        let kafka_record = BaseRecord::to(topic.as_str()).payload(&payload).key(&key_bytes);
+        if let Err((e, _)) = producer.send(kafka_record) {
+            error!("failed enqueuing cdc record (seq {}) to Kafka: {}", seq, e);
+        }
+        producer.poll(Duration::from_millis(0));
+    }
+    producer.flush(Duration::from_secs(5));
+}
@@ -1,20 +1,137 @@
 //! The configuration info for a server
 //!
-//! a `KVServerConfig` can be constructed with either default value (for test use) or a
-//! `clap::ArgMatches` (for CLI program use). The configuration can then be passed and used.
+//! a `KVServerConfig` can be constructed with default values (for test use), a `clap::ArgMatches`
+//! (for CLI program use), a TOML file (for deployment use), or layered across all three plus
+//! environment variables via `from_layered`, which is what the `kvserver` binary actually uses.
 
 use clap::{ArgMatches, value_t};
 use log::info;
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
 
 const DEFAULT_FILENAME: &str = "data.kv";
 const DEFAULT_LISTEN_PORT: u16 = 1926;
 const DEFAULT_THREADS: u16 = 4;
+const DEFAULT_CDC_BUFFER_SIZE: u32 = 1024;
+const DEFAULT_CDC_DROP_ON_OVERFLOW: bool = true;
+const DEFAULT_MAX_IN_FLIGHT_CONNECTIONS: u32 = 256;
+
+/// The error type used by the config module
+#[derive(Debug)]
+pub struct ConfigError {
+    description: String
+}
+
+impl ConfigError {
+    pub fn new(description: &str) -> Self {
+        ConfigError { description: description.to_owned() }
+    }
+
+    /// Builds the error raised when two sources disagree on the value of the same setting
+    fn conflict(key: &str, source_a: &str, value_a: &str, source_b: &str, value_b: &str) -> Self {
+        ConfigError::new(&format!(
+            "setting '{}' is specified differently by {} ('{}') and {} ('{}'), please remove one of them",
+            key, source_a, value_a, source_b, value_b
+        ))
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "config error: {}", self.description)
+    }
+}
+
+impl Error for ConfigError {
+}
 
 /// Configuration info needed for running a KV server, see its field for futher information
 pub struct KVServerConfig {
     pub db_file: String,
     pub listen_port: u16,
-    pub threads: u16
+    pub threads: u16,
+    /// If set, also accept RESP (redis protocol) connections on this port, see `kvserver::resp`
+    pub resp_listen_port: Option<u16>,
+    /// Path to a PEM certificate (chain) used to terminate TLS connections
+    pub tls_cert: Option<String>,
+    /// Path to the PEM private key matching `tls_cert`
+    pub tls_key: Option<String>,
+    /// Port the TLS listener binds to when TLS is configured; defaults to `listen_port`
+    pub tls_listen_port: Option<u16>,
+    /// When set, the server only accepts TLS connections and the plaintext listener is not bound
+    pub ssl_only: bool,
+    /// Path to a PEM file of CA certificates; when set, the TLS listener requires every client to
+    /// present a certificate signed by one of them (mutual TLS) instead of accepting any client
+    pub tls_client_ca: Option<String>,
+    /// If set, serve Prometheus text exposition metrics (see `kvserver::metrics`) over an HTTP
+    /// listener on this port
+    pub metrics_port: Option<u16>,
+    /// If set, accept plain VarInt length-framed `Request`/`ServerReplyChunk` connections (see
+    /// `kvserver::framing`) on this port -- the same dispatch as the chunktp listeners, but with no
+    /// per-chunk acknowledgement handshake
+    pub raw_port: Option<u16>,
+    /// Comma-separated Kafka bootstrap server list for the change-data-capture sink; must be set
+    /// together with `cdc_kafka_topic` to enable CDC, see `kvserver::cdc`
+    pub cdc_kafka_brokers: Option<String>,
+    /// Kafka topic the CDC sink publishes every PUT/DEL mutation to
+    pub cdc_kafka_topic: Option<String>,
+    /// Number of mutations the CDC sink buffers between request-handling threads and its
+    /// background Kafka producer thread before applying `cdc_drop_on_overflow`
+    pub cdc_buffer_size: u32,
+    /// When the CDC buffer is full: `true` drops the mutation (logging a warning) so a slow
+    /// broker can't stall request handling, `false` blocks the request-handling thread until
+    /// there's room
+    pub cdc_drop_on_overflow: bool,
+    /// Max number of connections queued for or being handled at once, see `kvserver::backpressure`;
+    /// once reached, the accept loop blocks until a connection finishes, exerting backpressure
+    /// instead of letting unbounded work pile up behind the thread pool
+    pub max_in_flight_connections: u32
+}
+
+/// The same settings as `KVServerConfig`, but every field optional, used as the common shape for
+/// each individual config source (environment, TOML file, CLI flags) before they're merged
+#[derive(Deserialize, Default, Clone)]
+#[serde(default)]
+struct PartialConfig {
+    db_file: Option<String>,
+    listen_port: Option<u16>,
+    threads: Option<u16>,
+    resp_listen_port: Option<u16>,
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    tls_listen_port: Option<u16>,
+    ssl_only: Option<bool>,
+    tls_client_ca: Option<String>,
+    metrics_port: Option<u16>,
+    raw_port: Option<u16>,
+    cdc_kafka_brokers: Option<String>,
+    cdc_kafka_topic: Option<String>,
+    cdc_buffer_size: Option<u32>,
+    cdc_drop_on_overflow: Option<bool>,
+    max_in_flight_connections: Option<u32>
+}
+
+/// Picks the single value for a setting out of several sources, in priority order unaffected
+/// (order does not matter for the result, only for which source name appears first in the error
+/// message); returns `Err` naming `key` if two sources disagree on differing values
+fn resolve<T: Clone + PartialEq + Display>(key: &str, candidates: &[(&str, Option<T>)]) -> Result<Option<T>, ConfigError> {
+    let mut chosen: Option<(&str, T)> = None;
+    for (source, value) in candidates {
+        if let Some(value) = value {
+            match &chosen {
+                None => chosen = Some((source, value.clone())),
+                Some((chosen_source, chosen_value)) if chosen_value != value => {
+                    return Err(ConfigError::conflict(key, chosen_source, &chosen_value.to_string(), source, &value.to_string()));
+                },
+                Some(_) => {}
+            }
+        }
+    }
+    Ok(chosen.map(|(_, value)| value))
 }
 
 impl KVServerConfig {
@@ -23,7 +140,20 @@ impl KVServerConfig {
         KVServerConfig {
             db_file: DEFAULT_FILENAME.to_owned(),
             listen_port: DEFAULT_LISTEN_PORT,
-            threads: DEFAULT_THREADS }
+            threads: DEFAULT_THREADS,
+            resp_listen_port: None,
+            tls_cert: None,
+            tls_key: None,
+            tls_listen_port: None,
+            ssl_only: false,
+            tls_client_ca: None,
+            metrics_port: None,
+            raw_port: None,
+            cdc_kafka_brokers: None,
+            cdc_kafka_topic: None,
+            cdc_buffer_size: DEFAULT_CDC_BUFFER_SIZE,
+            cdc_drop_on_overflow: DEFAULT_CDC_DROP_ON_OVERFLOW,
+            max_in_flight_connections: DEFAULT_MAX_IN_FLIGHT_CONNECTIONS }
     }
 
     /// Creates a `KVServerConfig` from command line arguments (`clap::ArgMatches`).
@@ -33,7 +163,20 @@ impl KVServerConfig {
     /// for thread pool size. If there are some formal parameters missing from the command line
     /// argument, or the arguments provided from command line does not satisfy the type
     /// requirements, this function will generate some `Info` level log, and use default values to
-    /// fill in these parameters.
+    /// fill in these parameters. An optional `resp-port` formal parameter of type `u16` enables the
+    /// RESP front-end on that port; if it's missing, the RESP front-end is left disabled.
+    /// `tls-cert`/`tls-key`/`tls-port` configure TLS termination, and the `ssl-only` flag rejects
+    /// plaintext connections by not binding the plaintext listener at all. `tls-client-ca`
+    /// additionally turns on mutual TLS, requiring every client to present a certificate signed by
+    /// one of the CAs in that PEM file. `metrics-port` exposes Prometheus metrics (see
+    /// `kvserver::metrics`) over its own HTTP listener; if missing, no metrics are served.
+    /// `raw-port` accepts plain VarInt length-framed connections (see `kvserver::framing`) in
+    /// addition to the chunktp listeners; if missing, it is not bound.
+    /// `cdc-kafka-brokers`/`cdc-kafka-topic` enable the change-data-capture sink (see
+    /// `kvserver::cdc`); `cdc-buffer-size`/`cdc-drop-on-overflow` tune its bounded channel,
+    /// falling back to their defaults if missing. `max-in-flight-connections` caps how many
+    /// connections the accept loop lets through before blocking (see `kvserver::backpressure`),
+    /// also falling back to its default if missing.
     pub fn from_arg_matches(matches: ArgMatches) -> Self {
         let db_file = value_t!(matches, "dbfile", String).unwrap_or_else(|_| {
                 info!("no valid dbfile provided from commandline, using default file name '{}'", DEFAULT_FILENAME);
@@ -47,6 +190,192 @@ impl KVServerConfig {
                 info!("no valid thread pool size provided from commandline, using default size {}", DEFAULT_THREADS);
                 DEFAULT_THREADS
             });
-        KVServerConfig { db_file, listen_port, threads }
+        let resp_listen_port = value_t!(matches, "resp-port", u16).ok();
+        let tls_cert = value_t!(matches, "tls-cert", String).ok();
+        let tls_key = value_t!(matches, "tls-key", String).ok();
+        let tls_listen_port = value_t!(matches, "tls-port", u16).ok();
+        let ssl_only = matches.is_present("ssl-only");
+        let tls_client_ca = value_t!(matches, "tls-client-ca", String).ok();
+        let metrics_port = value_t!(matches, "metrics-port", u16).ok();
+        let raw_port = value_t!(matches, "raw-port", u16).ok();
+        let cdc_kafka_brokers = value_t!(matches, "cdc-kafka-brokers", String).ok();
+        let cdc_kafka_topic = value_t!(matches, "cdc-kafka-topic", String).ok();
+        let cdc_buffer_size = value_t!(matches, "cdc-buffer-size", u32).unwrap_or(DEFAULT_CDC_BUFFER_SIZE);
+        let cdc_drop_on_overflow = value_t!(matches, "cdc-drop-on-overflow", bool).unwrap_or(DEFAULT_CDC_DROP_ON_OVERFLOW);
+        let max_in_flight_connections = value_t!(matches, "max-in-flight-connections", u32)
+            .unwrap_or(DEFAULT_MAX_IN_FLIGHT_CONNECTIONS);
+        KVServerConfig {
+            db_file, listen_port, threads, resp_listen_port, tls_cert, tls_key, tls_listen_port, ssl_only, tls_client_ca,
+            metrics_port, raw_port, cdc_kafka_brokers, cdc_kafka_topic, cdc_buffer_size, cdc_drop_on_overflow, max_in_flight_connections
+        }
+    }
+
+    /// Creates a `KVServerConfig` from a TOML config file at `path`, recognizing the same keys as
+    /// the `KVServerConfig` fields (`db_file`, `listen_port`, `threads`, `resp_listen_port`,
+    /// `tls_cert`, `tls_key`, `tls_listen_port`, `ssl_only`, `tls_client_ca`, `metrics_port`,
+    /// `raw_port`, `cdc_kafka_brokers`, `cdc_kafka_topic`, `cdc_buffer_size`, `cdc_drop_on_overflow`,
+    /// `max_in_flight_connections`); any key missing from the file falls back to the same default
+    /// used by `from_default`.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        Ok(Self::apply_defaults(Self::parse_file(path)?))
+    }
+
+    /// Resolves a `KVServerConfig` by merging, for every setting, its environment variable
+    /// (`KVSERVER_*`), its entry in the TOML file named by the `--config` CLI flag (if any), and
+    /// its CLI flag, in that order of increasing specificity. If two of these sources specify the
+    /// same setting with differing values, returns a `ConfigError` naming the conflicting setting
+    /// instead of silently picking one, so misconfiguration is caught at startup rather than
+    /// producing a server running with unexpected settings.
+    pub fn from_layered(matches: ArgMatches) -> Result<Self, ConfigError> {
+        let env = Self::from_env();
+        let file = match value_t!(matches, "config", String).ok() {
+            Some(path) => Self::parse_file(Path::new(&path))?,
+            None => PartialConfig::default()
+        };
+        let cli = Self::partial_from_matches(&matches);
+
+        macro_rules! merge {
+            ($field:ident) => {
+                resolve(stringify!($field), &[
+                    ("an environment variable", env.$field.clone()),
+                    ("the config file", file.$field.clone()),
+                    ("a command line flag", cli.$field.clone())
+                ])?
+            };
+        }
+
+        let db_file: Option<String> = merge!(db_file);
+        let listen_port: Option<u16> = merge!(listen_port);
+        let threads: Option<u16> = merge!(threads);
+        let resp_listen_port: Option<u16> = merge!(resp_listen_port);
+        let tls_cert: Option<String> = merge!(tls_cert);
+        let tls_key: Option<String> = merge!(tls_key);
+        let tls_listen_port: Option<u16> = merge!(tls_listen_port);
+        let ssl_only: Option<bool> = merge!(ssl_only);
+        let tls_client_ca: Option<String> = merge!(tls_client_ca);
+        let metrics_port: Option<u16> = merge!(metrics_port);
+        let raw_port: Option<u16> = merge!(raw_port);
+        let cdc_kafka_brokers: Option<String> = merge!(cdc_kafka_brokers);
+        let cdc_kafka_topic: Option<String> = merge!(cdc_kafka_topic);
+        let cdc_buffer_size: Option<u32> = merge!(cdc_buffer_size);
+        let cdc_drop_on_overflow: Option<bool> = merge!(cdc_drop_on_overflow);
+        let max_in_flight_connections: Option<u32> = merge!(max_in_flight_connections);
+
+        Ok(Self::apply_defaults(PartialConfig {
+            db_file, listen_port, threads, resp_listen_port, tls_cert, tls_key, tls_listen_port, ssl_only, tls_client_ca,
+            metrics_port, raw_port, cdc_kafka_brokers, cdc_kafka_topic, cdc_buffer_size, cdc_drop_on_overflow, max_in_flight_connections
+        }))
+    }
+
+    /// Reads and parses a TOML config file into a `PartialConfig`, without filling in defaults
+    fn parse_file(path: &Path) -> Result<PartialConfig, ConfigError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| ConfigError::new(&format!("failed reading config file '{}': {}", path.display(), e)))?;
+        toml::from_str(&contents)
+            .map_err(|e| ConfigError::new(&format!("failed parsing config file '{}': {}", path.display(), e)))
+    }
+
+    /// Reads the `KVSERVER_*` environment variables into a `PartialConfig`
+    fn from_env() -> PartialConfig {
+        PartialConfig {
+            db_file: std::env::var("KVSERVER_DB_FILE").ok(),
+            listen_port: std::env::var("KVSERVER_LISTEN_PORT").ok().and_then(|v| v.parse().ok()),
+            threads: std::env::var("KVSERVER_THREADS").ok().and_then(|v| v.parse().ok()),
+            resp_listen_port: std::env::var("KVSERVER_RESP_LISTEN_PORT").ok().and_then(|v| v.parse().ok()),
+            tls_cert: std::env::var("KVSERVER_TLS_CERT").ok(),
+            tls_key: std::env::var("KVSERVER_TLS_KEY").ok(),
+            tls_listen_port: std::env::var("KVSERVER_TLS_LISTEN_PORT").ok().and_then(|v| v.parse().ok()),
+            ssl_only: std::env::var("KVSERVER_SSL_ONLY").ok().and_then(|v| v.parse().ok()),
+            tls_client_ca: std::env::var("KVSERVER_TLS_CLIENT_CA").ok(),
+            metrics_port: std::env::var("KVSERVER_METRICS_PORT").ok().and_then(|v| v.parse().ok()),
+            raw_port: std::env::var("KVSERVER_RAW_PORT").ok().and_then(|v| v.parse().ok()),
+            cdc_kafka_brokers: std::env::var("KVSERVER_CDC_KAFKA_BROKERS").ok(),
+            cdc_kafka_topic: std::env::var("KVSERVER_CDC_KAFKA_TOPIC").ok(),
+            cdc_buffer_size: std::env::var("KVSERVER_CDC_BUFFER_SIZE").ok().and_then(|v| v.parse().ok()),
+            cdc_drop_on_overflow: std::env::var("KVSERVER_CDC_DROP_ON_OVERFLOW").ok().and_then(|v| v.parse().ok()),
+            max_in_flight_connections: std::env::var("KVSERVER_MAX_IN_FLIGHT_CONNECTIONS").ok().and_then(|v| v.parse().ok())
+        }
+    }
+
+    /// Reads the CLI flags that are actually present into a `PartialConfig`, leaving anything
+    /// absent as `None` instead of falling back to a default (unlike `from_arg_matches`), so
+    /// `from_layered` can tell "not specified" apart from "specified as the default value"
+    fn partial_from_matches(matches: &ArgMatches) -> PartialConfig {
+        PartialConfig {
+            db_file: value_t!(matches, "dbfile", String).ok(),
+            listen_port: value_t!(matches, "port", u16).ok(),
+            threads: value_t!(matches, "threads", u16).ok(),
+            resp_listen_port: value_t!(matches, "resp-port", u16).ok(),
+            tls_cert: value_t!(matches, "tls-cert", String).ok(),
+            tls_key: value_t!(matches, "tls-key", String).ok(),
+            tls_listen_port: value_t!(matches, "tls-port", u16).ok(),
+            ssl_only: if matches.is_present("ssl-only") { Some(true) } else { None },
+            tls_client_ca: value_t!(matches, "tls-client-ca", String).ok(),
+            metrics_port: value_t!(matches, "metrics-port", u16).ok(),
+            raw_port: value_t!(matches, "raw-port", u16).ok(),
+            cdc_kafka_brokers: value_t!(matches, "cdc-kafka-brokers", String).ok(),
+            cdc_kafka_topic: value_t!(matches, "cdc-kafka-topic", String).ok(),
+            cdc_buffer_size: value_t!(matches, "cdc-buffer-size", u32).ok(),
+            cdc_drop_on_overflow: value_t!(matches, "cdc-drop-on-overflow", bool).ok(),
+            max_in_flight_connections: value_t!(matches, "max-in-flight-connections", u32).ok()
+        }
+    }
+
+    /// Fills in any field left `None` in `partial` with the same defaults `from_default` uses
+    fn apply_defaults(partial: PartialConfig) -> Self {
+        KVServerConfig {
+            db_file: partial.db_file.unwrap_or_else(|| DEFAULT_FILENAME.to_owned()),
+            listen_port: partial.listen_port.unwrap_or(DEFAULT_LISTEN_PORT),
+            threads: partial.threads.unwrap_or(DEFAULT_THREADS),
+            resp_listen_port: partial.resp_listen_port,
+            tls_cert: partial.tls_cert,
+            tls_key: partial.tls_key,
+            tls_listen_port: partial.tls_listen_port,
+            ssl_only: partial.ssl_only.unwrap_or(false),
+            tls_client_ca: partial.tls_client_ca,
+            metrics_port: partial.metrics_port,
+            raw_port: partial.raw_port,
+            cdc_kafka_brokers: partial.cdc_kafka_brokers,
+            cdc_kafka_topic: partial.cdc_kafka_topic,
+            cdc_buffer_size: partial.cdc_buffer_size.unwrap_or(DEFAULT_CDC_BUFFER_SIZE),
+            cdc_drop_on_overflow: partial.cdc_drop_on_overflow.unwrap_or(DEFAULT_CDC_DROP_ON_OVERFLOW),
+            max_in_flight_connections: partial.max_in_flight_connections.unwrap_or(DEFAULT_MAX_IN_FLIGHT_CONNECTIONS)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_config {
+    use super::*;
+
+    #[test]
+    fn resolve_returns_none_when_all_sources_absent() {
+        let result = resolve::<u16>("threads", &[("environment", None), ("config file", None)]).unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn resolve_accepts_matching_values_from_multiple_sources() {
+        let result = resolve("threads", &[("environment", Some(4u16)), ("config file", Some(4u16))]).unwrap();
+        assert_eq!(result, Some(4));
+    }
+
+    #[test]
+    fn resolve_rejects_conflicting_values() {
+        let err = resolve("threads", &[("environment", Some(4u16)), ("command line flag", Some(8u16))]).unwrap_err();
+        assert!(err.to_string().contains("threads"));
+    }
+
+    #[test]
+    fn parse_file_reads_present_fields_and_leaves_others_none() {
+        let path = Path::new("test_config_file.toml");
+        fs::write(path, "db_file = \"custom.kv\"\nlisten_port = 4000\n").unwrap();
+
+        let partial = KVServerConfig::parse_file(path).unwrap();
+        assert_eq!(partial.db_file, Some("custom.kv".to_owned()));
+        assert_eq!(partial.listen_port, Some(4000));
+        assert_eq!(partial.threads, None);
+
+        let _ = fs::remove_file(path);
     }
 }
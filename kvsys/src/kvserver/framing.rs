@@ -0,0 +1,311 @@
+//! Variable-length-integer (LEB128-style) length-delimited framing for the wire protocol
+//!
+//! `Request::deserialize_from` and `ReplyChunk::deserialize` (see `kvserver::protocol`) both take a
+//! buffer that must already contain exactly one message; that's fine for chunktp, which frames
+//! messages itself, but anything reading a raw `TcpStream` (or any other byte stream) has no way to
+//! tell where one message ends and the next begins. `Encoder` prepends a VarInt length prefix to an
+//! outgoing payload, and `Decoder` pulls one complete, length-prefixed frame at a time out of a
+//! growing buffer, the same framing idea chunktp's own fixed 2-byte size prefix uses, just with a
+//! variable-width prefix instead. `RawFramedConnection` wraps a stream with exactly this framing
+//! and implements `FramedConnection`, so `kvserver::handle_connection` can serve it directly
+//! alongside `ChunktpsConnection` -- see the `raw_port` listener in `kvserver::run_server`.
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+
+use crate::chunktps::ChunktpsConnection;
+
+/// The error type used by the framing module
+#[derive(Debug)]
+pub struct FramingError {
+    description: String
+}
+
+impl FramingError {
+    pub fn new(description: &str) -> Self {
+        FramingError { description: description.to_owned() }
+    }
+}
+
+impl Display for FramingError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "framing error: {}", self.description)
+    }
+}
+
+impl Error for FramingError {
+}
+
+/// A VarInt prefix is capped at 5 bytes (7 bits per byte), enough to cover the full `u32` range
+const MAX_VARINT_BYTES: usize = 5;
+
+/// Encodes `value` as a LEB128-style VarInt: 7 bits per byte, low group first, with the high bit
+/// (0x80) set on every byte except the last
+fn encode_varint(value: u32) -> Vec<u8> {
+    let mut ret = Vec::new();
+    let mut value = value;
+    loop {
+        let mut byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value != 0 {
+            byte |= 0x80;
+        }
+        ret.push(byte);
+        if value == 0 {
+            return ret;
+        }
+    }
+}
+
+/// Outcome of scanning a VarInt prefix out of the front of a buffer
+enum VarintScan {
+    /// The decoded value, and how many bytes its prefix occupied
+    Complete(u32, usize),
+    /// Fewer than `MAX_VARINT_BYTES` bytes are buffered and none of them terminates the VarInt yet
+    Incomplete
+}
+
+/// Scans a VarInt off the front of `buf` without consuming it; the caller decides how many bytes
+/// to drain once it also knows how long the frame body is.
+fn decode_varint(buf: &[u8]) -> Result<VarintScan, FramingError> {
+    let mut value: u32 = 0;
+    for (i, &byte) in buf.iter().take(MAX_VARINT_BYTES).enumerate() {
+        value |= ((byte & 0x7F) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(VarintScan::Complete(value, i + 1));
+        }
+    }
+    if buf.len() >= MAX_VARINT_BYTES {
+        Err(FramingError::new("varint length prefix longer than 5 bytes"))
+    } else {
+        Ok(VarintScan::Incomplete)
+    }
+}
+
+/// Prepends a VarInt length prefix to a payload, ready to write to the wire
+pub struct Encoder;
+
+impl Encoder {
+    /// Encodes `payload` into a standalone frame: a VarInt length prefix followed by the payload
+    pub fn encode(payload: &[u8]) -> Vec<u8> {
+        let mut ret = encode_varint(payload.len() as u32);
+        ret.extend_from_slice(payload);
+        ret
+    }
+}
+
+/// One attempt at pulling a frame out of the front of a buffer, see `Decoder::decode`
+pub enum DecodeOutcome {
+    /// A complete frame was found: its body, and how many bytes (prefix + body) it occupied
+    Frame(Vec<u8>, usize),
+    /// Not enough bytes are buffered yet to know the frame's length, or to contain its full body
+    NeedMoreBytes
+}
+
+/// Pulls complete, length-prefixed frames out of a growing byte buffer fed by arbitrary reads
+pub struct Decoder {
+    max_length: u32
+}
+
+impl Decoder {
+    /// Creates a `Decoder` that rejects any frame announcing a length greater than `max_length`
+    pub fn new(max_length: u32) -> Self {
+        Decoder { max_length }
+    }
+
+    /// Tries to pull one complete frame out of the front of `buf`.
+    ///
+    /// Returns `DecodeOutcome::Frame` if a whole frame is present at the front of `buf`; the
+    /// caller is responsible for draining the returned byte count from the front of its own
+    /// buffer before calling `decode` again. Returns `DecodeOutcome::NeedMoreBytes` if the prefix
+    /// or body isn't fully buffered yet, leaving `buf` untouched so the caller can read more bytes
+    /// and retry. Returns `Err` if the VarInt prefix overflows 5 bytes, or if its announced length
+    /// exceeds `max_length`.
+    pub fn decode(&self, buf: &[u8]) -> Result<DecodeOutcome, FramingError> {
+        let (length, prefix_len) = match decode_varint(buf)? {
+            VarintScan::Incomplete => return Ok(DecodeOutcome::NeedMoreBytes),
+            VarintScan::Complete(length, prefix_len) => (length, prefix_len)
+        };
+
+        if length > self.max_length {
+            return Err(FramingError::new(&format!("frame length {} exceeds max_length {}", length, self.max_length)));
+        }
+
+        let length = length as usize;
+        let total_len = prefix_len + length;
+        if buf.len() < total_len {
+            return Ok(DecodeOutcome::NeedMoreBytes);
+        }
+
+        Ok(DecodeOutcome::Frame(buf[prefix_len..total_len].to_vec(), total_len))
+    }
+}
+
+/// Implemented by any already-framed duplex connection `kvserver::handle_connection` can pull
+/// whole requests from and push whole replies to. `ChunktpsConnection` is the production
+/// transport; `RawFramedConnection` lets the exact same dispatch logic run over the plain
+/// VarInt-framed `raw_port` listener instead.
+pub trait FramedConnection {
+    fn read_chunk(&mut self) -> Result<Vec<u8>, Box<dyn Error>>;
+    fn write_chunk(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error>>;
+}
+
+impl<S: Read + Write> FramedConnection for ChunktpsConnection<S> {
+    fn read_chunk(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        ChunktpsConnection::read_chunk(self)
+    }
+
+    fn write_chunk(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        ChunktpsConnection::write_chunk(self, data)
+    }
+}
+
+/// Pulls framed payloads directly off a raw byte stream using `Encoder`/`Decoder`, with no
+/// per-chunk acknowledgement -- unlike `ChunktpsConnection`, nothing stops a future write until a
+/// previous one is acknowledged, so this trades chunktp's flow-control handshake for a plain,
+/// one-directional length-prefixed stream that's easy for a minimal client to speak
+pub struct RawFramedConnection<S: Read + Write> {
+    stream: S,
+    max_length: u32,
+    buf: Vec<u8>
+}
+
+impl<S: Read + Write> RawFramedConnection<S> {
+    /// Wraps `stream`, rejecting any frame announcing a length greater than `max_length`
+    pub fn new(stream: S, max_length: u32) -> Self {
+        RawFramedConnection { stream, max_length, buf: Vec::new() }
+    }
+
+    /// Reads off the stream, accumulating bytes until `Decoder` reports a complete frame, then
+    /// drains and returns its body
+    pub fn read_chunk(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        let decoder = Decoder::new(self.max_length);
+        loop {
+            match decoder.decode(&self.buf)? {
+                DecodeOutcome::Frame(frame, consumed) => {
+                    self.buf.drain(..consumed);
+                    return Ok(frame);
+                },
+                DecodeOutcome::NeedMoreBytes => {
+                    let mut read_buf = [0u8; 4096];
+                    let n = self.stream.read(&mut read_buf)?;
+                    if n == 0 {
+                        return Err(Box::new(FramingError::new("connection closed mid-frame")));
+                    }
+                    self.buf.extend_from_slice(&read_buf[..n]);
+                }
+            }
+        }
+    }
+
+    /// Writes `data` to the stream as a single length-prefixed frame
+    pub fn write_chunk(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        self.stream.write_all(&Encoder::encode(&data))?;
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> FramedConnection for RawFramedConnection<S> {
+    fn read_chunk(&mut self) -> Result<Vec<u8>, Box<dyn Error>> {
+        RawFramedConnection::read_chunk(self)
+    }
+
+    fn write_chunk(&mut self, data: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        RawFramedConnection::write_chunk(self, data)
+    }
+}
+
+#[cfg(test)]
+mod test_framing {
+    use crate::kvserver::framing::{Decoder, DecodeOutcome, Encoder};
+
+    #[test]
+    fn encode_then_decode_roundtrips() {
+        for payload in [vec![], vec![0u8; 1], vec![0u8; 127], vec![0u8; 128], vec![0u8; 16384]] {
+            let framed = Encoder::encode(&payload);
+            let decoder = Decoder::new(u32::MAX);
+            match decoder.decode(&framed).unwrap() {
+                DecodeOutcome::Frame(frame, consumed) => {
+                    assert_eq!(frame, payload);
+                    assert_eq!(consumed, framed.len());
+                },
+                DecodeOutcome::NeedMoreBytes => panic!("expected a complete frame")
+            }
+        }
+    }
+
+    #[test]
+    fn decode_reports_need_more_bytes_on_incomplete_prefix() {
+        let decoder = Decoder::new(u32::MAX);
+        // 0x80 has its high bit set, so the varint isn't terminated yet
+        match decoder.decode(&[0x80]).unwrap() {
+            DecodeOutcome::NeedMoreBytes => (),
+            DecodeOutcome::Frame(..) => panic!("prefix is incomplete, should not yield a frame")
+        }
+    }
+
+    #[test]
+    fn decode_reports_need_more_bytes_on_incomplete_body() {
+        let framed = Encoder::encode(&[1, 2, 3, 4, 5]);
+        let decoder = Decoder::new(u32::MAX);
+        match decoder.decode(&framed[..framed.len() - 1]).unwrap() {
+            DecodeOutcome::NeedMoreBytes => (),
+            DecodeOutcome::Frame(..) => panic!("body is incomplete, should not yield a frame")
+        }
+    }
+
+    #[test]
+    fn decode_rejects_varint_longer_than_five_bytes() {
+        let decoder = Decoder::new(u32::MAX);
+        let buf = [0x80u8, 0x80, 0x80, 0x80, 0x80, 0x01];
+        assert!(decoder.decode(&buf).is_err());
+    }
+
+    #[test]
+    fn decode_rejects_length_over_max_length() {
+        let framed = Encoder::encode(&[0u8; 100]);
+        let decoder = Decoder::new(10);
+        assert!(decoder.decode(&framed).is_err());
+    }
+
+    #[test]
+    fn decode_leaves_extra_trailing_bytes_for_the_next_frame() {
+        let first = Encoder::encode(&[1, 2, 3]);
+        let second = Encoder::encode(&[4, 5]);
+        let mut buf = first.clone();
+        buf.extend_from_slice(&second);
+
+        let decoder = Decoder::new(u32::MAX);
+        match decoder.decode(&buf).unwrap() {
+            DecodeOutcome::Frame(frame, consumed) => {
+                assert_eq!(frame, vec![1, 2, 3]);
+                assert_eq!(consumed, first.len());
+                match decoder.decode(&buf[consumed..]).unwrap() {
+                    DecodeOutcome::Frame(frame, consumed) => {
+                        assert_eq!(frame, vec![4, 5]);
+                        assert_eq!(consumed, second.len());
+                    },
+                    DecodeOutcome::NeedMoreBytes => panic!("expected a complete second frame")
+                }
+            },
+            DecodeOutcome::NeedMoreBytes => panic!("expected a complete first frame")
+        }
+    }
+
+    #[test]
+    fn raw_framed_connection_writes_frames_the_decoder_can_read_back() {
+        use crate::kvserver::framing::RawFramedConnection;
+        use std::io::Cursor;
+
+        let mut writer = RawFramedConnection::new(Cursor::new(Vec::new()), u32::MAX);
+        writer.write_chunk(b"hello".to_vec()).unwrap();
+        writer.write_chunk(b"world".to_vec()).unwrap();
+        let written = writer.stream.into_inner();
+
+        let mut reader = RawFramedConnection::new(Cursor::new(written), u32::MAX);
+        assert_eq!(reader.read_chunk().unwrap(), b"hello".to_vec());
+        assert_eq!(reader.read_chunk().unwrap(), b"world".to_vec());
+    }
+}
@@ -0,0 +1,154 @@
+//! Prometheus metrics for observing a running server: request counters by operation, error counts,
+//! bytes read/written through `ChunktpsConnection`, a latency histogram of `handle_connection`
+//! operations, and gauges for active connection-handling workers and current `storage_engine` lock
+//! contention.
+//!
+//! `run_server` builds one `ServerMetrics` and shares it into every connection, which records
+//! against it from within `handle_connection`'s match arms. If `metrics_port` is configured, a
+//! small HTTP listener serves `render`'s Prometheus text exposition output to any client that
+//! connects (e.g. a Prometheus scrape).
+
+use std::time::Instant;
+
+use prometheus::{Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+
+/// Timer returned by `ServerMetrics::start_op`; `observe` records the elapsed time against the
+/// matching `kvserver_op_latency_seconds` bucket
+pub struct OpTimer {
+    histogram: Histogram,
+    started_at: Instant
+}
+
+impl OpTimer {
+    /// Records the time elapsed since the timer was started
+    pub fn observe(self) {
+        self.histogram.observe(self.started_at.elapsed().as_secs_f64());
+    }
+}
+
+/// Every counter/gauge/histogram the server reports, registered into their own `Registry` so
+/// `render` doesn't also pick up metrics from unrelated libraries sharing the process
+pub struct ServerMetrics {
+    registry: Registry,
+    requests_total: IntCounterVec,
+    errors_total: IntCounter,
+    bytes_read_total: IntCounter,
+    bytes_written_total: IntCounter,
+    op_latency_seconds: HistogramVec,
+    active_workers: IntGauge,
+    storage_lock_contention: IntGauge,
+    in_flight_connections: IntGauge
+}
+
+impl ServerMetrics {
+    /// Builds a fresh, independently-registered set of metrics, all starting at zero
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let requests_total = IntCounterVec::new(
+            Opts::new("kvserver_requests_total", "Number of requests handled, by operation"), &["op"]
+        ).unwrap();
+        let errors_total = IntCounter::new(
+            "kvserver_errors_total", "Number of requests that resulted in an error reply"
+        ).unwrap();
+        let bytes_read_total = IntCounter::new(
+            "kvserver_bytes_read_total", "Bytes read through ChunktpsConnection"
+        ).unwrap();
+        let bytes_written_total = IntCounter::new(
+            "kvserver_bytes_written_total", "Bytes written through ChunktpsConnection"
+        ).unwrap();
+        let op_latency_seconds = HistogramVec::new(
+            HistogramOpts::new("kvserver_op_latency_seconds", "handle_connection operation latency, by operation"), &["op"]
+        ).unwrap();
+        let active_workers = IntGauge::new(
+            "kvserver_active_workers", "Connections currently being served"
+        ).unwrap();
+        let storage_lock_contention = IntGauge::new(
+            "kvserver_storage_lock_contention", "Threads currently waiting to acquire the storage_engine lock"
+        ).unwrap();
+        let in_flight_connections = IntGauge::new(
+            "kvserver_in_flight_connections",
+            "Connections currently queued for or being handled, bounded by max_in_flight_connections"
+        ).unwrap();
+
+        registry.register(Box::new(requests_total.clone())).unwrap();
+        registry.register(Box::new(errors_total.clone())).unwrap();
+        registry.register(Box::new(bytes_read_total.clone())).unwrap();
+        registry.register(Box::new(bytes_written_total.clone())).unwrap();
+        registry.register(Box::new(op_latency_seconds.clone())).unwrap();
+        registry.register(Box::new(active_workers.clone())).unwrap();
+        registry.register(Box::new(storage_lock_contention.clone())).unwrap();
+        registry.register(Box::new(in_flight_connections.clone())).unwrap();
+
+        ServerMetrics {
+            registry, requests_total, errors_total, bytes_read_total, bytes_written_total,
+            op_latency_seconds, active_workers, storage_lock_contention, in_flight_connections
+        }
+    }
+
+    /// Counts one request for `op` (e.g. "get", "put", "scan")
+    pub fn inc_requests(&self, op: &str) {
+        self.requests_total.with_label_values(&[op]).inc();
+    }
+
+    /// Counts one request that resulted in an error reply
+    pub fn inc_errors(&self) {
+        self.errors_total.inc();
+    }
+
+    /// Adds to the total bytes read through `ChunktpsConnection`
+    pub fn add_bytes_read(&self, n: u64) {
+        self.bytes_read_total.inc_by(n);
+    }
+
+    /// Adds to the total bytes written through `ChunktpsConnection`
+    pub fn add_bytes_written(&self, n: u64) {
+        self.bytes_written_total.inc_by(n);
+    }
+
+    /// Starts a latency timer for `op`; call `observe` on the returned `OpTimer` once the
+    /// operation completes
+    pub fn start_op(&self, op: &str) -> OpTimer {
+        OpTimer { histogram: self.op_latency_seconds.with_label_values(&[op]), started_at: Instant::now() }
+    }
+
+    /// Marks one more connection as actively being handled; pair with `worker_finished`
+    pub fn worker_started(&self) {
+        self.active_workers.inc();
+    }
+
+    /// Marks a connection as no longer being handled
+    pub fn worker_finished(&self) {
+        self.active_workers.dec();
+    }
+
+    /// Marks one more thread as waiting to acquire the `storage_engine` lock; pair with
+    /// `lock_acquired`
+    pub fn lock_contended(&self) {
+        self.storage_lock_contention.inc();
+    }
+
+    /// Marks a thread as having acquired the `storage_engine` lock it was waiting for
+    pub fn lock_acquired(&self) {
+        self.storage_lock_contention.dec();
+    }
+
+    /// Records the current number of connections queued for or being handled, see
+    /// `kvserver::backpressure::ConnectionLimiter`
+    pub fn set_in_flight_connections(&self, n: u32) {
+        self.in_flight_connections.set(n as i64);
+    }
+
+    /// Renders every registered metric in Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+}
+
+impl Default for ServerMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
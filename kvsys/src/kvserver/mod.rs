@@ -1,65 +1,286 @@
+pub mod backpressure;
+pub mod cdc;
 pub mod config;
+pub mod framing;
+pub mod metrics;
 pub mod protocol;
-pub use config::KVServerConfig;
+pub mod resp;
+pub mod resync;
+pub mod watch;
+pub use config::{KVServerConfig, ConfigError};
 pub use protocol::{SCAN, PUT, GET, DEL};
 
-use std::{fs, path, process};
+use std::{fs, path, process, thread};
+use std::io::{BufRead, BufReader, Write};
 use std::net::{TcpListener, SocketAddr, TcpStream};
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 
 use crate::kvstorage::{KVStorage};
 use crate::threadpool::ThreadPool;
-use crate::kvserver::protocol::{Request, ServerReplyChunk, KV_PAIR_SERIALIZED_SIZE};
+use crate::kvserver::backpressure::ConnectionLimiter;
+use crate::kvserver::cdc::{CdcOp, CdcSink};
+use crate::kvserver::framing::{FramedConnection, RawFramedConnection};
+use crate::kvserver::metrics::ServerMetrics;
+use crate::kvserver::protocol::{Op, OpOutcome, Request, ServerReplyChunk};
+use crate::kvserver::resync::ScanResumeRegistry;
+use crate::kvserver::watch::WatchRegistry;
 use crate::chunktps::{ChunktpsConnection, CHUNK_MAX_SIZE};
+use crate::chunktps::tls::TlsAcceptor;
 
 use log::{error, warn, info};
 use std::error::Error;
 
+/// Max announced frame length the `raw_port` listener accepts before rejecting a connection.
+/// Pinned to `CHUNK_MAX_SIZE`, not just "comfortably large": a value `Put` in over this limit
+/// could never be handed back out in a `CHUNK_MAX_SIZE`-bounded chunktp reply chunk, so a value
+/// accepted here but rejected there would make that key's `Get`/`Scan` permanently fail on every
+/// other listener. Keeping the two in lock-step means every listener agrees on the largest
+/// request/reply that can ever round-trip, regardless of which one a client connects through.
+const RAW_FRAME_MAX_LENGTH: u32 = CHUNK_MAX_SIZE as u32;
+
 fn create_storage_engine(config: &KVServerConfig) -> Arc<RwLock<KVStorage>> {
     let path = path::Path::new(&config.db_file);
-    let is_existing = path.exists();
-    let file = if is_existing {
-        fs::File::open(path)
-    } else {
-        fs::File::create(path)
-    }.unwrap_or_else(
-        | e | {
-            error!("failed opening or creating file {}", config.db_file);
-            error!("extra info: {}", e.description());
-            process::exit(1)
-        }
-    );
-
-    let storage = if is_existing {
-        KVStorage::from_existing_file(file).unwrap_or_else(| e | {
+    let storage = if path.exists() {
+        KVStorage::from_existing_file(path).unwrap_or_else(| e | {
             error!("error setting up storage engine from existing file {}", config.db_file);
             error!("extra info: {}", e.description());
             error!("this is usually because you have a corrupted database file, or using a non-kv file");
             process::exit(1)
         })
     } else {
-        KVStorage::new(file)
+        KVStorage::new(path).unwrap_or_else(| e | {
+            error!("failed creating file {}", config.db_file);
+            error!("extra info: {}", e.description());
+            process::exit(1)
+        })
     };
 
     Arc::new(RwLock::new(storage))
 }
 
 fn bind_tcp_listener(config: &KVServerConfig) -> TcpListener {
-    let addr = SocketAddr::from(([127, 0, 0, 1], config.listen_port));
+    bind_port_listener(config.listen_port, "plaintext listener")
+}
+
+fn bind_port_listener(port: u16, purpose: &str) -> TcpListener {
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
     TcpListener::bind(&addr).unwrap_or_else(
         | e | {
-            error!("failed binding to port {}", config.listen_port);
+            error!("failed binding {} to port {}", purpose, port);
             error!("extra info: {}", e.description());
             process::exit(1)
         }
     )
 }
 
+fn build_tls_acceptor(config: &KVServerConfig) -> Option<Arc<TlsAcceptor>> {
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let client_ca = config.tls_client_ca.as_ref().map(|p| path::Path::new(p));
+            let acceptor = TlsAcceptor::from_files(path::Path::new(cert), path::Path::new(key), client_ca)
+                .unwrap_or_else(| e | {
+                    error!("failed setting up TLS from cert '{}' and key '{}'", cert, key);
+                    error!("extra info: {}", e.description());
+                    process::exit(1)
+                });
+            Some(Arc::new(acceptor))
+        },
+        (None, None) => None,
+        _ => {
+            error!("tls_cert and tls_key must be configured together");
+            process::exit(1)
+        }
+    }
+}
+
+fn build_cdc_sink(config: &KVServerConfig) -> Option<Arc<CdcSink>> {
+    match (&config.cdc_kafka_brokers, &config.cdc_kafka_topic) {
+        (Some(brokers), Some(topic)) => {
+            let sink = CdcSink::new(brokers, topic, config.cdc_buffer_size as usize, config.cdc_drop_on_overflow)
+                .unwrap_or_else(|e| {
+                    error!("failed setting up the change-data-capture sink for brokers '{}', topic '{}'", brokers, topic);
+                    error!("extra info: {}", e.description());
+                    process::exit(1)
+                });
+            Some(Arc::new(sink))
+        },
+        (None, None) => None,
+        _ => {
+            error!("cdc_kafka_brokers and cdc_kafka_topic must be configured together");
+            process::exit(1)
+        }
+    }
+}
+
+/// Reads and discards a minimal HTTP request (just enough to find the blank line ending the
+/// headers), then replies with `metrics.render()` as a `text/plain` body before closing the
+/// connection. Good enough to be scraped by Prometheus; nothing else about the request matters
+fn handle_metrics_connection(mut stream: TcpStream, metrics: Arc<ServerMetrics>) {
+    let mut reader = BufReader::new(match stream.try_clone() {
+        Ok(s) => s,
+        Err(_) => return
+    });
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) if line == "\r\n" || line == "\n" => break,
+            Ok(_) => {}
+        }
+    }
+
+    let body = metrics.render();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(), body
+    );
+    let _ = stream.write_all(response.as_bytes());
+}
+
 pub fn run_server(config: KVServerConfig) {
     let storage = create_storage_engine(&config);
-    let tcp_listener = bind_tcp_listener(&config);
+    let watch_registry = Arc::new(WatchRegistry::new());
+    let scan_resume_registry = Arc::new(ScanResumeRegistry::new());
+    let cdc_sink = build_cdc_sink(&config);
+    let metrics = Arc::new(ServerMetrics::new());
+    let connection_limiter = Arc::new(ConnectionLimiter::new(config.max_in_flight_connections));
     let pool = ThreadPool::new(config.threads as usize);
 
+    let tls_acceptor = build_tls_acceptor(&config);
+    if config.ssl_only && tls_acceptor.is_none() {
+        error!("ssl_only was requested but no tls_cert/tls_key were configured");
+        process::exit(1);
+    }
+
+    if let Some(acceptor) = tls_acceptor.clone() {
+        let tls_port = config.tls_listen_port.unwrap_or(config.listen_port);
+        let storage = storage.clone();
+        let watch_registry = watch_registry.clone();
+        let scan_resume_registry = scan_resume_registry.clone();
+        let cdc_sink = cdc_sink.clone();
+        let metrics = metrics.clone();
+        let connection_limiter = connection_limiter.clone();
+        let tls_pool = ThreadPool::new(config.threads as usize);
+        let tls_listener = bind_port_listener(tls_port, "TLS listener");
+        let run_tls_loop = move || {
+            for stream in tls_listener.incoming() {
+                if let Err(e) = stream {
+                    warn!("an TCP error occurred on the TLS listener, extra info: {}", e.description());
+                    info!("automatically gave up and moved to next iteration");
+                    break;
+                }
+                let stream = stream.unwrap();
+                let storage = storage.clone();
+                let watch_registry = watch_registry.clone();
+                let scan_resume_registry = scan_resume_registry.clone();
+                let cdc_sink = cdc_sink.clone();
+                let metrics = metrics.clone();
+                let permit = connection_limiter.acquire(&metrics);
+                let acceptor = acceptor.clone();
+                tls_pool.execute(move || {
+                    let _permit = permit;
+                    let tls_stream = match acceptor.accept(stream) {
+                        Ok(s) => s,
+                        Err(e) => {
+                            warn!("TLS handshake failed, extra info: {}", e);
+                            return;
+                        }
+                    };
+                    if let Err(e) = handle_connection(ChunktpsConnection::new(tls_stream), storage, watch_registry, scan_resume_registry, cdc_sink, metrics) {
+                        warn!("an error occurred when processing request");
+                        info!("detailed error info: {}", e.description());
+                    }
+                });
+            }
+        };
+        if config.ssl_only {
+            // the TLS listener is the only listener, so run it on the main thread
+            run_tls_loop();
+            return;
+        } else {
+            thread::spawn(run_tls_loop);
+        }
+    }
+
+    let tcp_listener = bind_tcp_listener(&config);
+
+    if let Some(resp_port) = config.resp_listen_port {
+        let storage = storage.clone();
+        let resp_listener = bind_port_listener(resp_port, "RESP front-end");
+        let resp_pool = ThreadPool::new(config.threads as usize);
+        thread::spawn(move || {
+            for stream in resp_listener.incoming() {
+                if let Err(e) = stream {
+                    warn!("an TCP error occurred on the RESP front-end, extra info: {}", e.description());
+                    info!("automatically gave up and moved to next iteration");
+                    break;
+                }
+                let stream = stream.unwrap();
+                let storage = storage.clone();
+                resp_pool.execute(move || {
+                    if let Err(e) = handle_resp_connection(stream, storage) {
+                        warn!("an error occurred when processing a RESP request");
+                        info!("detailed error info: {}", e.description());
+                    }
+                });
+            }
+        });
+    }
+
+    if let Some(metrics_port) = config.metrics_port {
+        let metrics = metrics.clone();
+        let metrics_listener = bind_port_listener(metrics_port, "metrics endpoint");
+        thread::spawn(move || {
+            for stream in metrics_listener.incoming() {
+                if let Err(e) = stream {
+                    warn!("an TCP error occurred on the metrics endpoint, extra info: {}", e.description());
+                    info!("automatically gave up and moved to next iteration");
+                    break;
+                }
+                let stream = stream.unwrap();
+                let metrics = metrics.clone();
+                thread::spawn(move || handle_metrics_connection(stream, metrics));
+            }
+        });
+    }
+
+    if let Some(raw_port) = config.raw_port {
+        let storage = storage.clone();
+        let watch_registry = watch_registry.clone();
+        let scan_resume_registry = scan_resume_registry.clone();
+        let cdc_sink = cdc_sink.clone();
+        let metrics = metrics.clone();
+        let connection_limiter = connection_limiter.clone();
+        let raw_listener = bind_port_listener(raw_port, "raw framed listener");
+        let raw_pool = ThreadPool::new(config.threads as usize);
+        thread::spawn(move || {
+            for stream in raw_listener.incoming() {
+                if let Err(e) = stream {
+                    warn!("an TCP error occurred on the raw framed listener, extra info: {}", e.description());
+                    info!("automatically gave up and moved to next iteration");
+                    break;
+                }
+                let stream = stream.unwrap();
+                let storage = storage.clone();
+                let watch_registry = watch_registry.clone();
+                let scan_resume_registry = scan_resume_registry.clone();
+                let cdc_sink = cdc_sink.clone();
+                let metrics = metrics.clone();
+                let permit = connection_limiter.acquire(&metrics);
+                raw_pool.execute(move || {
+                    let _permit = permit;
+                    let conn = RawFramedConnection::new(stream, RAW_FRAME_MAX_LENGTH);
+                    if let Err(e) = handle_connection(conn, storage, watch_registry, scan_resume_registry, cdc_sink, metrics) {
+                        warn!("an error occurred when processing a raw framed request");
+                        info!("detailed error info: {}", e.description());
+                    }
+                });
+            }
+        });
+    }
+
     for stream in tcp_listener.incoming() {
         if let Err(e) = stream {
             warn!("an TCP error occurred, extra info: {}", e.description());
@@ -69,8 +290,14 @@ pub fn run_server(config: KVServerConfig) {
         let stream = stream.unwrap();
 
         let storage = storage.clone();
+        let watch_registry = watch_registry.clone();
+        let scan_resume_registry = scan_resume_registry.clone();
+        let cdc_sink = cdc_sink.clone();
+        let metrics = metrics.clone();
+        let permit = connection_limiter.acquire(&metrics);
         pool.execute(move || {
-            if let Err(e) = handle_connection(stream, storage) {
+            let _permit = permit;
+            if let Err(e) = handle_connection(ChunktpsConnection::new(stream), storage, watch_registry, scan_resume_registry, cdc_sink, metrics) {
                 warn!("an error occurred when processing request");
                 info!("detailed error info: {}", e.description());
             }
@@ -78,33 +305,251 @@ pub fn run_server(config: KVServerConfig) {
     }
 }
 
-fn handle_connection(stream: TcpStream, storage_engine: Arc<RwLock<KVStorage>>) -> Result<(), Box<dyn Error>> {
-    let mut chunktps = ChunktpsConnection::new(stream);
+/// Label used for the `op` dimension of the request/latency metrics
+fn request_op_label(request: &Request) -> &'static str {
+    match request {
+        Request::Get(_) => "get",
+        Request::Put(_, _) => "put",
+        Request::Del(_) => "del",
+        Request::Scan(_, _) => "scan",
+        Request::Batch(_) => "batch",
+        Request::Watch(_, _) => "watch",
+        Request::WatchRange(_, _, _) => "watch_range",
+        Request::ScanPage(_, _, _, _) => "scan_page",
+        Request::Resume(_, _) => "resume",
+        Request::Close => "close"
+    }
+}
+
+/// Dispatches requests off an already-framed connection -- a `ChunktpsConnection` from the
+/// plaintext/TLS listeners, or a `RawFramedConnection` from the `raw_port` listener; either way,
+/// `chunktps.read_chunk`/`write_chunk` hand back and accept whole, delimited payloads
+fn handle_connection<C: FramedConnection>(
+    mut chunktps: C, storage_engine: Arc<RwLock<KVStorage>>, watch_registry: Arc<WatchRegistry>,
+    scan_resume_registry: Arc<ScanResumeRegistry>, cdc_sink: Option<Arc<CdcSink>>, metrics: Arc<ServerMetrics>
+) -> Result<(), Box<dyn Error>> {
+    metrics.worker_started();
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        loop {
+            let request_bytes = chunktps.read_chunk()?;
+            metrics.add_bytes_read(request_bytes.len() as u64);
+            let request = Request::deserialize_from(request_bytes)?;
+
+            let op = request_op_label(&request);
+            metrics.inc_requests(op);
+            let timer = metrics.start_op(op);
+
+            match request {
+                Request::Get(key) => {
+                    let maybe_value = {
+                        metrics.lock_contended();
+                        let storage_engine = storage_engine.read().unwrap();
+                        metrics.lock_acquired();
+                        storage_engine.get(&key)
+                    };
+                    let reply = ServerReplyChunk::SingleValue(maybe_value).serialize();
+                    metrics.add_bytes_written(reply.len() as u64);
+                    chunktps.write_chunk(reply)?;
+                },
+                Request::Put(key, value) => {
+                    {
+                        metrics.lock_contended();
+                        let mut storage_engine = storage_engine.write().unwrap();
+                        metrics.lock_acquired();
+                        storage_engine.put(&key, &value);
+                    }
+                    watch_registry.notify(&key, Some(Arc::new(value.clone())));
+                    if let Some(cdc_sink) = &cdc_sink {
+                        cdc_sink.publish(CdcOp::Put, key, Some(value));
+                    }
+                },
+                Request::Del(key) => {
+                    let rows_effected = {
+                        metrics.lock_contended();
+                        let mut storage_engine = storage_engine.write().unwrap();
+                        metrics.lock_acquired();
+                        storage_engine.delete(&key)
+                    };
+                    watch_registry.notify(&key, None);
+                    if let Some(cdc_sink) = &cdc_sink {
+                        cdc_sink.publish(CdcOp::Del, key, None);
+                    }
+                    let reply = ServerReplyChunk::Number(rows_effected).serialize();
+                    metrics.add_bytes_written(reply.len() as u64);
+                    chunktps.write_chunk(reply)?;
+                },
+                Request::Scan(key1, key2) => {
+                    let scan_id = scan_resume_registry.start(key2);
+                    let reply = ServerReplyChunk::ScanStarted(scan_id).serialize();
+                    metrics.add_bytes_written(reply.len() as u64);
+                    chunktps.write_chunk(reply)?;
+
+                    // -1 for the `KVPairs` chunk-kind tag `ServerReplyChunk::serialize` prepends
+                    let max_bytes_per_chunk = CHUNK_MAX_SIZE - 1;
+                    let scan_result = {
+                        metrics.lock_contended();
+                        let storage_engine = storage_engine.read().unwrap();
+                        metrics.lock_acquired();
+                        storage_engine.scan_chunked(&key1, &key2, max_bytes_per_chunk)
+                    };
+                    for group in &scan_result {
+                        let reply = ServerReplyChunk::KVPairs(group).serialize();
+                        metrics.add_bytes_written(reply.len() as u64);
+                        chunktps.write_chunk(reply)?;
+                        scan_resume_registry.ack(scan_id);
+                    }
+                    scan_resume_registry.finish(scan_id);
+                    chunktps.write_chunk(ServerReplyChunk::ScanEnd.serialize())?;
+                },
+                Request::Batch(ops) => {
+                    // a single write lock is taken for the whole batch, so the ops are applied
+                    // atomically relative to every other connection's reads and writes. The
+                    // watch/CDC notifications those ops trigger are only queued up here and fired
+                    // after the lock is released below, same as the non-batch Put/Del arms, so a
+                    // slow watcher or a full (blocking) CDC channel can't hold the storage lock
+                    // hostage for every other connection.
+                    let mut outcomes = Vec::with_capacity(ops.len());
+                    let mut pending_notifies = Vec::new();
+                    let mut pending_cdc = Vec::new();
+                    {
+                        metrics.lock_contended();
+                        let mut storage_engine = storage_engine.write().unwrap();
+                        metrics.lock_acquired();
+                        for op in &ops {
+                            outcomes.push(match op {
+                                Op::Put(key, value) => {
+                                    storage_engine.put(key, value);
+                                    pending_notifies.push((*key, Some(Arc::new(value.clone()))));
+                                    pending_cdc.push((CdcOp::Put, *key, Some(value.clone())));
+                                    OpOutcome::Put
+                                },
+                                Op::Get(key) => OpOutcome::Value(storage_engine.get(key)),
+                                Op::Del(key) => {
+                                    let rows_effected = storage_engine.delete(key);
+                                    pending_notifies.push((*key, None));
+                                    pending_cdc.push((CdcOp::Del, *key, None));
+                                    OpOutcome::Deleted(rows_effected)
+                                }
+                            });
+                        }
+                    }
+                    for (key, value) in pending_notifies {
+                        watch_registry.notify(&key, value);
+                    }
+                    if let Some(cdc_sink) = &cdc_sink {
+                        for (op, key, value) in pending_cdc {
+                            cdc_sink.publish(op, key, value);
+                        }
+                    }
+                    let reply = ServerReplyChunk::BatchResult(outcomes).serialize();
+                    metrics.add_bytes_written(reply.len() as u64);
+                    chunktps.write_chunk(reply)?;
+                },
+                Request::Watch(key, timeout_ms) => {
+                    let event = watch_registry.wait_key(key, Duration::from_millis(timeout_ms));
+                    let reply = ServerReplyChunk::Watch(event).serialize();
+                    metrics.add_bytes_written(reply.len() as u64);
+                    chunktps.write_chunk(reply)?;
+                },
+                Request::WatchRange(key1, key2, timeout_ms) => {
+                    let event = watch_registry.wait_range(key1, key2, Duration::from_millis(timeout_ms));
+                    let reply = ServerReplyChunk::Watch(event).serialize();
+                    metrics.add_bytes_written(reply.len() as u64);
+                    chunktps.write_chunk(reply)?;
+                },
+                Request::ScanPage(key1, key2, limit, after_token) => {
+                    let (pairs, next_token) = {
+                        metrics.lock_contended();
+                        let storage_engine = storage_engine.read().unwrap();
+                        metrics.lock_acquired();
+                        storage_engine.scan_page(&key1, &key2, limit, after_token.as_ref())
+                    };
+                    let reply = ServerReplyChunk::Page(&pairs, next_token).serialize();
+                    metrics.add_bytes_written(reply.len() as u64);
+                    chunktps.write_chunk(reply)?;
+                },
+                Request::Resume(scan_id, last_key) => {
+                    match scan_resume_registry.resume(scan_id) {
+                        Some(key2) => {
+                            // -1 for the `KVPairs` chunk-kind tag `ServerReplyChunk::serialize` prepends
+                            let max_bytes_per_chunk = CHUNK_MAX_SIZE - 1;
+                            let scan_result = {
+                                metrics.lock_contended();
+                                let storage_engine = storage_engine.read().unwrap();
+                                metrics.lock_acquired();
+                                storage_engine.scan_chunked_after(&last_key, &key2, max_bytes_per_chunk)
+                            };
+                            for group in &scan_result {
+                                let reply = ServerReplyChunk::KVPairs(group).serialize();
+                                metrics.add_bytes_written(reply.len() as u64);
+                                chunktps.write_chunk(reply)?;
+                                scan_resume_registry.ack(scan_id);
+                            }
+                            scan_resume_registry.finish(scan_id);
+                            chunktps.write_chunk(ServerReplyChunk::ScanEnd.serialize())?;
+                        },
+                        None => {
+                            metrics.inc_errors();
+                            chunktps.write_chunk(ServerReplyChunk::Error.serialize())?;
+                        }
+                    }
+                },
+                Request::Close => {
+                    return Ok(())
+                }
+            }
+
+            timer.observe();
+        }
+    })();
+    metrics.worker_finished();
+    result
+}
+
+/// Serve a single connection speaking RESP (see `kvserver::resp`) instead of chunktp framing, so
+/// off-the-shelf redis clients and tooling can talk to Project-KV directly
+fn handle_resp_connection(stream: TcpStream, storage_engine: Arc<RwLock<KVStorage>>) -> Result<(), Box<dyn Error>> {
+    use std::io::Write;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
     loop {
-        match Request::deserialize_from(chunktps.read_chunk()?)? {
+        let request = match resp::read_request(&mut reader) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                let _ = writer.write_all(&resp::encode_error(&e.to_string()));
+                return Err(e);
+            }
+        };
+        match request {
             Request::Get(key) => {
                 let maybe_value = storage_engine.read().unwrap().get(&key);
-                chunktps.write_chunk(ServerReplyChunk::SingleValue(maybe_value).serialize())?;
+                writer.write_all(&resp::encode_get_reply(maybe_value))?;
             },
             Request::Put(key, value) => {
                 storage_engine.write().unwrap().put(&key, &value);
+                writer.write_all(&resp::encode_simple_string("OK"))?;
             },
             Request::Del(key) => {
                 let rows_effected = storage_engine.write().unwrap().delete(&key);
-                chunktps.write_chunk(ServerReplyChunk::Number(rows_effected).serialize())?;
+                writer.write_all(&resp::encode_integer(rows_effected as i64))?;
             },
             Request::Scan(key1, key2) => {
-                const ROW_PER_CHUNK: usize = (CHUNK_MAX_SIZE - 1) / KV_PAIR_SERIALIZED_SIZE;
                 let scan_result = storage_engine.read().unwrap().scan(&key1, &key2);
-                for i in (0..scan_result.len()).step_by(ROW_PER_CHUNK) {
-                    let slice = if i + ROW_PER_CHUNK < scan_result.len() {
-                        &scan_result[i..i+ROW_PER_CHUNK]
-                    } else {
-                        &scan_result[i..scan_result.len()]
-                    };
-                    chunktps.write_chunk(ServerReplyChunk::KVPairs(slice).serialize())?;
-                }
-                chunktps.write_chunk(vec![])?;
+                writer.write_all(&resp::encode_scan_reply(&scan_result))?;
+            },
+            Request::Batch(_) => {
+                writer.write_all(&resp::encode_error("batch requests are not supported over RESP"))?;
+            },
+            Request::Watch(_, _) | Request::WatchRange(_, _, _) => {
+                writer.write_all(&resp::encode_error("watch requests are not supported over RESP"))?;
+            },
+            Request::ScanPage(_, _, _, _) => {
+                writer.write_all(&resp::encode_error("paginated scan requests are not supported over RESP"))?;
+            },
+            Request::Resume(_, _) => {
+                writer.write_all(&resp::encode_error("resume requests are not supported over RESP"))?;
             },
             Request::Close => {
                 return Ok(())
@@ -119,7 +564,10 @@ mod test_server_handle_connection {
     use crate::util::{gen_key, gen_value, gen_key_n};
     use crate::chunktps::ChunktpsConnection;
     use crate::kvserver::handle_connection;
-    use crate::kvserver::protocol::{Request, ReplyChunk};
+    use crate::kvserver::metrics::ServerMetrics;
+    use crate::kvserver::protocol::{ClientOpOutcome, Op, Request, ReplyChunk, ScanAccumulator};
+    use crate::kvserver::resync::ScanResumeRegistry;
+    use crate::kvserver::watch::WatchRegistry;
 
     use std::sync::{Arc, RwLock};
     use std::net::{TcpStream, TcpListener};
@@ -130,13 +578,12 @@ mod test_server_handle_connection {
     #[test]
     fn test_handle_put() {
         let _ = fs::remove_file("test_put.kv");
-        let log_file = fs::File::create("test_put.kv").unwrap();
-        let storage_engine = Arc::new(RwLock::new(KVStorage::new(log_file)));
+        let storage_engine = Arc::new(RwLock::new(KVStorage::new("test_put.kv").unwrap()));
         let storage_engine_clone = storage_engine.clone();
         let t = thread::spawn(move || {
             let tcp_listener = TcpListener::bind("127.0.0.1:1972").unwrap();
             let (tcp_stream, _) = tcp_listener.accept().unwrap();
-            handle_connection(tcp_stream, storage_engine_clone).unwrap();
+            handle_connection(ChunktpsConnection::new(tcp_stream), storage_engine_clone, Arc::new(WatchRegistry::new()), Arc::new(ScanResumeRegistry::new()), None, Arc::new(ServerMetrics::new())).unwrap();
         });
 
         let key = gen_key();
@@ -155,8 +602,7 @@ mod test_server_handle_connection {
     #[test]
     fn test_handle_get() {
         let _ = fs::remove_file("test_get.kv");
-        let log_file = fs::File::create("test_get.kv").unwrap();
-        let storage_engine = Arc::new(RwLock::new(KVStorage::new(log_file)));
+        let storage_engine = Arc::new(RwLock::new(KVStorage::new("test_get.kv").unwrap()));
         let key = gen_key();
         let value = gen_value();
         storage_engine.write().unwrap().put(&key, &value);
@@ -164,7 +610,7 @@ mod test_server_handle_connection {
         let t = thread::spawn(move || {
             let tcp_listener = TcpListener::bind("127.0.0.1:2333").unwrap();
             let (tcp_stream, _) = tcp_listener.accept().unwrap();
-            handle_connection(tcp_stream, storage_engine_clone).unwrap();
+            handle_connection(ChunktpsConnection::new(tcp_stream), storage_engine_clone, Arc::new(WatchRegistry::new()), Arc::new(ScanResumeRegistry::new()), None, Arc::new(ServerMetrics::new())).unwrap();
         });
 
         thread::sleep(Duration::from_secs(1));
@@ -186,8 +632,7 @@ mod test_server_handle_connection {
     #[test]
     fn test_handle_scan() {
         let _ = fs::remove_file("test_scan.kv");
-        let log_file = fs::File::create("test_scan.kv").unwrap();
-        let storage_engine = Arc::new(RwLock::new(KVStorage::new(log_file)));
+        let storage_engine = Arc::new(RwLock::new(KVStorage::new("test_scan.kv").unwrap()));
         for i in 0..255 {
             let key = gen_key_n(i);
             let value = gen_value();
@@ -198,32 +643,285 @@ mod test_server_handle_connection {
         let t = thread::spawn(move || {
             let tcp_listener = TcpListener::bind("127.0.0.1:4396").unwrap();
             let (tcp_stream, _) = tcp_listener.accept().unwrap();
-            handle_connection(tcp_stream, storage_engine_clone).unwrap();
+            handle_connection(ChunktpsConnection::new(tcp_stream), storage_engine_clone, Arc::new(WatchRegistry::new()), Arc::new(ScanResumeRegistry::new()), None, Arc::new(ServerMetrics::new())).unwrap();
         });
         thread::sleep(Duration::from_secs(1));
         let tcp_stream = TcpStream::connect("127.0.0.1:4396").unwrap();
         let mut chunktps = ChunktpsConnection::new(tcp_stream);
         chunktps.write_chunk(Request::Scan(gen_key_n(0), gen_key_n(254)).serialize()).unwrap();
 
-        let mut total_data = 0;
+        match ReplyChunk::deserialize(chunktps.read_chunk().unwrap()).unwrap() {
+            ReplyChunk::ScanStarted(_) => (),
+            _ => panic!()
+        }
+
+        let mut acc = ScanAccumulator::new();
         loop {
             let data = chunktps.read_chunk().unwrap();
-            if data.len() == 0 {
+            if acc.push(data).unwrap() {
                 break;
             }
-            let chunk = ReplyChunk::deserialize(data).unwrap();
-            match chunk {
-                ReplyChunk::KVPairs(kv_pairs) => {
-                    total_data += kv_pairs.len();
-                    for (k, v) in kv_pairs.iter() {
-                        let value = storage_engine.read().unwrap().get(k).unwrap();
-                        assert_eq!(value.deref(), v);
+        }
+        let pairs = acc.into_pairs();
+        assert_eq!(pairs.len(), 254);
+        for (k, v) in pairs.iter() {
+            let value = storage_engine.read().unwrap().get(k).unwrap();
+            assert_eq!(value.deref(), v);
+        }
+
+        chunktps.write_chunk(Request::Close.serialize()).unwrap();
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_handle_scan_page() {
+        let _ = fs::remove_file("test_scan_page.kv");
+        let storage_engine = Arc::new(RwLock::new(KVStorage::new("test_scan_page.kv").unwrap()));
+        for i in 0..10 {
+            storage_engine.write().unwrap().put(&gen_key_n(i), &gen_value());
+        }
+
+        let storage_engine_clone = storage_engine.clone();
+        let t = thread::spawn(move || {
+            let tcp_listener = TcpListener::bind("127.0.0.1:7300").unwrap();
+            let (tcp_stream, _) = tcp_listener.accept().unwrap();
+            handle_connection(ChunktpsConnection::new(tcp_stream), storage_engine_clone, Arc::new(WatchRegistry::new()), Arc::new(ScanResumeRegistry::new()), None, Arc::new(ServerMetrics::new())).unwrap();
+        });
+        thread::sleep(Duration::from_secs(1));
+        let tcp_stream = TcpStream::connect("127.0.0.1:7300").unwrap();
+        let mut chunktps = ChunktpsConnection::new(tcp_stream);
+
+        let mut seen = Vec::new();
+        let mut after_token = None;
+        loop {
+            chunktps.write_chunk(
+                Request::ScanPage(gen_key_n(0), gen_key_n(10), 3, after_token).serialize()).unwrap();
+            match ReplyChunk::deserialize(chunktps.read_chunk().unwrap()).unwrap() {
+                ReplyChunk::Page(pairs, next_token) => {
+                    assert!(pairs.len() <= 3);
+                    seen.extend(pairs.into_iter().map(|(k, _)| k));
+                    if next_token.is_none() {
+                        break;
                     }
+                    after_token = next_token;
                 },
                 _ => panic!()
             }
         }
-        assert_eq!(total_data, 254);
+        assert_eq!(seen, (0..10).map(gen_key_n).collect::<Vec<_>>());
+
+        chunktps.write_chunk(Request::Close.serialize()).unwrap();
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_handle_resume_continues_after_a_dropped_scan() {
+        let _ = fs::remove_file("test_resume.kv");
+        let storage_engine = Arc::new(RwLock::new(KVStorage::new("test_resume.kv").unwrap()));
+        // enough rows that `scan_chunked` (bounded by `CHUNK_MAX_SIZE`) splits the scan into more
+        // than one `KVPairs` chunk, so reading just the first one is a genuine partial scan
+        for i in 0..255 {
+            storage_engine.write().unwrap().put(&gen_key_n(i), &gen_value());
+        }
+        let scan_resume_registry = Arc::new(ScanResumeRegistry::new());
+
+        let storage_engine_clone = storage_engine.clone();
+        let scan_resume_registry_clone = scan_resume_registry.clone();
+        let tcp_listener = TcpListener::bind("127.0.0.1:7301").unwrap();
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (tcp_stream, _) = tcp_listener.accept().unwrap();
+                let storage_engine = storage_engine_clone.clone();
+                let watch_registry = Arc::new(WatchRegistry::new());
+                let scan_resume_registry = scan_resume_registry_clone.clone();
+                thread::spawn(move || {
+                    handle_connection(ChunktpsConnection::new(tcp_stream), storage_engine, watch_registry, scan_resume_registry, None, Arc::new(ServerMetrics::new())).unwrap();
+                });
+            }
+        });
+
+        thread::sleep(Duration::from_secs(1));
+
+        let scan_id;
+        let last_key;
+        {
+            let mut chunktps = ChunktpsConnection::new(TcpStream::connect("127.0.0.1:7301").unwrap());
+            chunktps.write_chunk(Request::Scan(gen_key_n(0), gen_key_n(254)).serialize()).unwrap();
+
+            scan_id = match ReplyChunk::deserialize(chunktps.read_chunk().unwrap()).unwrap() {
+                ReplyChunk::ScanStarted(id) => id,
+                _ => panic!()
+            };
+
+            match ReplyChunk::deserialize(chunktps.read_chunk().unwrap()).unwrap() {
+                ReplyChunk::KVPairs(pairs) => {
+                    last_key = pairs.last().unwrap().0;
+                },
+                _ => panic!()
+            }
+            // connection drops here, before `ScanEnd` is read
+        }
+
+        let mut chunktps = ChunktpsConnection::new(TcpStream::connect("127.0.0.1:7301").unwrap());
+        chunktps.write_chunk(Request::Resume(scan_id, last_key).serialize()).unwrap();
+
+        let mut acc = ScanAccumulator::new();
+        loop {
+            let data = chunktps.read_chunk().unwrap();
+            if acc.push(data).unwrap() {
+                break;
+            }
+        }
+        let pairs = acc.into_pairs();
+        assert!(!pairs.is_empty());
+        for (k, _) in pairs.iter() {
+            assert!(k.encode() > last_key.encode());
+        }
+
+        chunktps.write_chunk(Request::Close.serialize()).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_handle_resume_with_unknown_scan_id_returns_an_error() {
+        let _ = fs::remove_file("test_resume_unknown.kv");
+        let storage_engine = Arc::new(RwLock::new(KVStorage::new("test_resume_unknown.kv").unwrap()));
+        let t = thread::spawn(move || {
+            let tcp_listener = TcpListener::bind("127.0.0.1:7302").unwrap();
+            let (tcp_stream, _) = tcp_listener.accept().unwrap();
+            handle_connection(ChunktpsConnection::new(tcp_stream), storage_engine, Arc::new(WatchRegistry::new()), Arc::new(ScanResumeRegistry::new()), None, Arc::new(ServerMetrics::new())).unwrap();
+        });
+
+        thread::sleep(Duration::from_secs(1));
+        let mut chunktps = ChunktpsConnection::new(TcpStream::connect("127.0.0.1:7302").unwrap());
+        chunktps.write_chunk(Request::Resume(1234, gen_key_n(0)).serialize()).unwrap();
+
+        match ReplyChunk::deserialize(chunktps.read_chunk().unwrap()).unwrap() {
+            ReplyChunk::Error => (),
+            _ => panic!()
+        }
+
+        chunktps.write_chunk(Request::Close.serialize()).unwrap();
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_handle_batch() {
+        let _ = fs::remove_file("test_batch.kv");
+        let storage_engine = Arc::new(RwLock::new(KVStorage::new("test_batch.kv").unwrap()));
+        let existing_key = gen_key();
+        let existing_value = gen_value();
+        storage_engine.write().unwrap().put(&existing_key, &existing_value);
+
+        let storage_engine_clone = storage_engine.clone();
+        let t = thread::spawn(move || {
+            let tcp_listener = TcpListener::bind("127.0.0.1:5217").unwrap();
+            let (tcp_stream, _) = tcp_listener.accept().unwrap();
+            handle_connection(ChunktpsConnection::new(tcp_stream), storage_engine_clone, Arc::new(WatchRegistry::new()), Arc::new(ScanResumeRegistry::new()), None, Arc::new(ServerMetrics::new())).unwrap();
+        });
+
+        thread::sleep(Duration::from_secs(1));
+        let tcp_stream = TcpStream::connect("127.0.0.1:5217").unwrap();
+        let mut chunktps = ChunktpsConnection::new(tcp_stream);
+
+        let put_key = gen_key();
+        let put_value = gen_value();
+        chunktps.write_chunk(Request::Batch(vec![
+            Op::Put(put_key, put_value),
+            Op::Get(existing_key),
+            Op::Del(existing_key)
+        ]).serialize()).unwrap();
+
+        let reply = ReplyChunk::deserialize(chunktps.read_chunk().unwrap()).unwrap();
+        match reply {
+            ReplyChunk::BatchResult(outcomes) => {
+                assert_eq!(outcomes.len(), 3);
+                match &outcomes[0] {
+                    ClientOpOutcome::Put => (),
+                    _ => panic!()
+                }
+                match &outcomes[1] {
+                    ClientOpOutcome::Value(Some(v)) => assert_eq!(v, &existing_value),
+                    _ => panic!()
+                }
+                match &outcomes[2] {
+                    ClientOpOutcome::Deleted(n) => assert_eq!(*n, 1),
+                    _ => panic!()
+                }
+            },
+            _ => panic!()
+        }
+
+        assert_eq!(storage_engine.read().unwrap().get(&put_key).unwrap().deref(), &put_value);
+        assert!(storage_engine.read().unwrap().get(&existing_key).is_none());
+
+        chunktps.write_chunk(Request::Close.serialize()).unwrap();
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn test_handle_watch_wakes_on_matching_put() {
+        let _ = fs::remove_file("test_watch.kv");
+        let storage_engine = Arc::new(RwLock::new(KVStorage::new("test_watch.kv").unwrap()));
+        let watch_registry = Arc::new(WatchRegistry::new());
+
+        let tcp_listener = TcpListener::bind("127.0.0.1:6101").unwrap();
+        let server = thread::spawn(move || {
+            for _ in 0..2 {
+                let (tcp_stream, _) = tcp_listener.accept().unwrap();
+                let storage_engine = storage_engine.clone();
+                let watch_registry = watch_registry.clone();
+                thread::spawn(move || {
+                    handle_connection(ChunktpsConnection::new(tcp_stream), storage_engine, watch_registry, Arc::new(ScanResumeRegistry::new()), None, Arc::new(ServerMetrics::new())).unwrap();
+                });
+            }
+        });
+
+        thread::sleep(Duration::from_secs(1));
+
+        let watch_key = gen_key();
+        let mut watcher = ChunktpsConnection::new(TcpStream::connect("127.0.0.1:6101").unwrap());
+        watcher.write_chunk(Request::Watch(watch_key, 5000).serialize()).unwrap();
+
+        thread::sleep(Duration::from_millis(200));
+
+        let value = gen_value();
+        let mut writer = ChunktpsConnection::new(TcpStream::connect("127.0.0.1:6101").unwrap());
+        writer.write_chunk(Request::Put(watch_key, value).serialize()).unwrap();
+
+        let reply = ReplyChunk::deserialize(watcher.read_chunk().unwrap()).unwrap();
+        match reply {
+            ReplyChunk::Watch(Some(event)) => {
+                assert_eq!(event.key, watch_key);
+                assert_eq!(event.value.unwrap(), value);
+            },
+            _ => panic!()
+        }
+
+        watcher.write_chunk(Request::Close.serialize()).unwrap();
+        writer.write_chunk(Request::Close.serialize()).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_handle_watch_times_out_with_no_matching_change() {
+        let _ = fs::remove_file("test_watch_timeout.kv");
+        let storage_engine = Arc::new(RwLock::new(KVStorage::new("test_watch_timeout.kv").unwrap()));
+        let t = thread::spawn(move || {
+            let tcp_listener = TcpListener::bind("127.0.0.1:6102").unwrap();
+            let (tcp_stream, _) = tcp_listener.accept().unwrap();
+            handle_connection(ChunktpsConnection::new(tcp_stream), storage_engine, Arc::new(WatchRegistry::new()), Arc::new(ScanResumeRegistry::new()), None, Arc::new(ServerMetrics::new())).unwrap();
+        });
+
+        thread::sleep(Duration::from_secs(1));
+        let mut chunktps = ChunktpsConnection::new(TcpStream::connect("127.0.0.1:6102").unwrap());
+        chunktps.write_chunk(Request::Watch(gen_key(), 200).serialize()).unwrap();
+
+        let reply = ReplyChunk::deserialize(chunktps.read_chunk().unwrap()).unwrap();
+        match reply {
+            ReplyChunk::Watch(None) => (),
+            _ => panic!()
+        }
 
         chunktps.write_chunk(Request::Close.serialize()).unwrap();
         t.join().unwrap();
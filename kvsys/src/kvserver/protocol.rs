@@ -5,7 +5,8 @@
 //! `ServerReplyChunk` APIs to serialize its reply chunks. The client can then use `ReplyChunk` APIs
 //! to deserialize a server reply chunk.
 
-use crate::kvstorage::{Key, Value, KEY_SIZE, VALUE_SIZE};
+use crate::kvserver::watch::WatchEvent;
+use crate::kvstorage::{Deserializable, Key, KVStorageError, Serializable, Value, KEY_SIZE};
 
 use std::sync::Arc;
 use std::fmt;
@@ -33,17 +34,46 @@ impl Display for ProtocolError {
 impl Error for ProtocolError {
 }
 
-/// Size of a `Key` - `Value` pair, basically an alias to `KEY_SIZE + VALUE_SIZE`.
-///
-/// The transmission protocol (for example, chunktp) may have limits on the data size. This
-/// constant can thus be used for "data per chunk" evaluation conveniently.
-pub const KV_PAIR_SERIALIZED_SIZE: usize = KEY_SIZE + VALUE_SIZE;
+impl From<KVStorageError> for ProtocolError {
+    fn from(e: KVStorageError) -> Self {
+        ProtocolError::new(&e.to_string())
+    }
+}
 
 const SCAN: u8 = b'S';
 const PUT: u8 = b'P';
 const GET: u8 = b'G';
 const DEL: u8 = b'D';
 const CLOSE: u8 = b'C';
+const BATCH: u8 = b'B';
+const WATCH: u8 = b'W';
+const WATCH_RANGE: u8 = b'R';
+const SCAN_PAGE: u8 = b'X';
+const RESUME: u8 = b'U';
+
+/// Writes `n` as 8 bytes, big-endian
+fn write_u64(buf: &mut Vec<u8>, n: u64) {
+    let mut n = n;
+    let mut arr = [0u8; 8];
+    for i in (0..8).rev() {
+        arr[i] = (n % 256) as u8;
+        n /= 256;
+    }
+    buf.extend_from_slice(&arr);
+}
+
+/// Reads 8 big-endian bytes starting at `*pos` as a `u64`, advancing `*pos` past them
+fn read_u64(buf: &[u8], pos: &mut usize) -> Result<u64, KVStorageError> {
+    if *pos + 8 > buf.len() {
+        return Err(KVStorageError::new("truncated number"));
+    }
+    let mut ret = 0u64;
+    for &byte in buf[*pos..*pos + 8].iter() {
+        ret = ret * 256 + byte as u64;
+    }
+    *pos += 8;
+    Ok(ret)
+}
 
 // Request format
 //  -- 1 byte functionality
@@ -52,120 +82,362 @@ const CLOSE: u8 = b'C';
 //     -- KEY_SIZE key2
 //     'P'
 //     -- KEY_SIZE key
-//     -- VALUE_SIZE value
+//     -- value, see `Value`'s `Serializable` impl (VarInt byte-length then the raw bytes)
 //     'G'
 //     -- KEY_SIZE key
 //     'D'
 //     -- KEY_SIZE key
+//     'B'
+//     -- any number of `Op`s (see `Op`'s `Serializable` impl), filling the rest of the chunk
+//     'W'
+//     -- KEY_SIZE key
+//     -- 8 bytes timeout_ms
+//     'R'
+//     -- KEY_SIZE key1
+//     -- KEY_SIZE key2
+//     -- 8 bytes timeout_ms
+//     'X'
+//     -- KEY_SIZE key1
+//     -- KEY_SIZE key2
+//     -- 8 bytes limit
+//     -- 1 byte presence (0 = nil, 1 = present)
+//     -- KEY_SIZE after_token, only if present
+//     'U'
+//     -- 8 bytes scan_id
+//     -- KEY_SIZE last_key
 //     'C'
 
+/// One operation within a `Request::Batch`, see its enumerators for further information
+#[derive(Clone)]
+pub enum Op {
+    Put(Key, Value),
+    Get(Key),
+    Del(Key)
+}
+
+impl Serializable for Op {
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        match self {
+            Op::Put(key, value) => {
+                buf.push(PUT);
+                key.write_to(buf);
+                value.write_to(buf);
+            },
+            Op::Get(key) => {
+                buf.push(GET);
+                key.write_to(buf);
+            },
+            Op::Del(key) => {
+                buf.push(DEL);
+                key.write_to(buf);
+            }
+        }
+    }
+}
+
+impl Deserializable for Op {
+    fn read_from(buf: &[u8], pos: &mut usize) -> Result<Self, KVStorageError> {
+        let kind = *buf.get(*pos).ok_or_else(|| KVStorageError::new("truncated op"))?;
+        *pos += 1;
+        match kind {
+            PUT => {
+                let key = Key::read_from(buf, pos)?;
+                let value = Value::read_from(buf, pos)?;
+                Ok(Op::Put(key, value))
+            },
+            GET => Ok(Op::Get(Key::read_from(buf, pos)?)),
+            DEL => Ok(Op::Del(Key::read_from(buf, pos)?)),
+            _ => Err(KVStorageError::new("incorrect op identifier"))
+        }
+    }
+}
+
 /// A request sent by client or received by server, see its enumerators for further information
 pub enum Request {
     Scan(Key, Key),
     Put(Key, Value),
     Get(Key),
     Del(Key),
+    /// A batch of `Op`s, applied by the server under a single lock acquisition so the whole batch
+    /// is atomic relative to other clients' writes -- see `ServerReplyChunk::BatchResult`
+    Batch(Vec<Op>),
+    /// Blocks the connection until `key` is PUT or DEL'd, or `timeout_ms` elapses -- see
+    /// `ServerReplyChunk::Watch` and `kvserver::watch::WatchRegistry`
+    Watch(Key, u64),
+    /// Like `Watch`, but matches any key in `[key1, key2)` instead of a single key
+    WatchRange(Key, Key, u64),
+    /// Fetches at most `limit` pairs within `[key1, key2)`, resuming strictly after `after_token`
+    /// (or starting at `key1` if `None`) -- see `ServerReplyChunk::Page` and `KVStorage::scan_page`
+    ScanPage(Key, Key, usize, Option<Key>),
+    /// Re-opens a `Request::Scan` stream identified by `scan_id` (see `ServerReplyChunk::ScanStarted`),
+    /// resuming strictly after `last_key` instead of re-sending already-flushed pairs -- see
+    /// `kvserver::resync::ScanResumeRegistry`
+    Resume(u64, Key),
     Close
 }
 
-impl Request {
-    /// Serialize a `Request` into a byte buffer
-    pub fn serialize(&self) -> Vec<u8> {
+impl Serializable for Request {
+    fn write_to(&self, buf: &mut Vec<u8>) {
         match self {
             Request::Scan(key1, key2) => {
-                let mut ret = vec![SCAN];
-                ret.append(&mut key1.serialize());
-                ret.append(&mut key2.serialize());
-                ret
+                buf.push(SCAN);
+                key1.write_to(buf);
+                key2.write_to(buf);
             },
             Request::Put(key, value) => {
-                let mut ret = vec![PUT];
-                ret.append(&mut key.serialize());
-                ret.append(&mut value.serialize());
-                ret
+                buf.push(PUT);
+                key.write_to(buf);
+                value.write_to(buf);
             },
             Request::Get(key) => {
-                let mut ret = vec![GET];
-                ret.append(&mut key.serialize());
-                ret
+                buf.push(GET);
+                key.write_to(buf);
             },
             Request::Del(key) => {
-                let mut ret = vec![DEL];
-                ret.append(&mut key.serialize());
-                ret
+                buf.push(DEL);
+                key.write_to(buf);
+            },
+            Request::Batch(ops) => {
+                buf.push(BATCH);
+                for op in ops {
+                    op.write_to(buf);
+                }
+            },
+            Request::Watch(key, timeout_ms) => {
+                buf.push(WATCH);
+                key.write_to(buf);
+                write_u64(buf, *timeout_ms);
+            },
+            Request::WatchRange(key1, key2, timeout_ms) => {
+                buf.push(WATCH_RANGE);
+                key1.write_to(buf);
+                key2.write_to(buf);
+                write_u64(buf, *timeout_ms);
+            },
+            Request::ScanPage(key1, key2, limit, after_token) => {
+                buf.push(SCAN_PAGE);
+                key1.write_to(buf);
+                key2.write_to(buf);
+                write_u64(buf, *limit as u64);
+                match after_token {
+                    Some(after_token) => {
+                        buf.push(PRESENT);
+                        after_token.write_to(buf);
+                    },
+                    None => buf.push(NIL)
+                }
+            },
+            Request::Resume(scan_id, last_key) => {
+                buf.push(RESUME);
+                write_u64(buf, *scan_id);
+                last_key.write_to(buf);
             },
             Request::Close => {
-                vec![CLOSE]
+                buf.push(CLOSE);
             }
         }
     }
+}
+
+impl Request {
+    /// Serialize a `Request` into a byte buffer
+    pub fn serialize(&self) -> Vec<u8> {
+        Serializable::serialize(self)
+    }
 
     /// Deserialize a byte buffer and construct a `Request` enum.
     ///
     /// Fails if the buffer does not meet the format of a `Request`, panics if the buffer is empty
     pub fn deserialize_from(raw: Vec<u8>) -> Result<Self, ProtocolError> {
         assert!(raw.len() > 0);
-        match raw[0] {
+        let mut pos = 1;
+        let request = match raw[0] {
             SCAN => {
-                if raw.len() != 1 + KEY_SIZE * 2 {
-                    Err(ProtocolError::new("incorrect content length"))
-                } else {
-                    let key1 = Key::from_slice(&raw[1..1+KEY_SIZE]);
-                    let key2 = Key::from_slice(&raw[1+KEY_SIZE..1+KEY_SIZE*2]);
-                    Ok(Request::Scan(key1, key2))
-                }
+                let key1 = Key::read_from(&raw, &mut pos)?;
+                let key2 = Key::read_from(&raw, &mut pos)?;
+                Request::Scan(key1, key2)
             },
             PUT => {
-                if raw.len() != 1 + KEY_SIZE + VALUE_SIZE {
-                    Err(ProtocolError::new("incorrect content length"))
-                } else {
-                    let key = Key::from_slice(&raw[1..1+KEY_SIZE]);
-                    let value = Value::from_slice(&raw[1+KEY_SIZE..1+KEY_SIZE+VALUE_SIZE]);
-                    Ok(Request::Put(key, value))
-                }
+                let key = Key::read_from(&raw, &mut pos)?;
+                let value = Value::read_from(&raw, &mut pos)?;
+                Request::Put(key, value)
             },
             GET => {
-                if raw.len() != 1 + KEY_SIZE {
-                    Err(ProtocolError::new("incorrect content length"))
-                } else {
-                    let key = Key::from_slice(&raw[1..1+KEY_SIZE]);
-                    Ok(Request::Get(key))
-                }
+                let key = Key::read_from(&raw, &mut pos)?;
+                Request::Get(key)
             },
             DEL => {
-                if raw.len() != 1 + KEY_SIZE {
-                    Err(ProtocolError::new("incorrect content length"))
-                } else {
-                    let key = Key::from_slice(&raw[1..1+KEY_SIZE]);
-                    Ok(Request::Del(key))
+                let key = Key::read_from(&raw, &mut pos)?;
+                Request::Del(key)
+            },
+            BATCH => {
+                let mut ops = Vec::new();
+                while pos < raw.len() {
+                    ops.push(Op::read_from(&raw, &mut pos)?);
                 }
+                Request::Batch(ops)
             },
-            CLOSE=> {
-                Ok(Request::Close)
-            }
-            _ => {
-                Err(ProtocolError::new("incorrect response chunk identifier"))
-            }
+            WATCH => {
+                let key = Key::read_from(&raw, &mut pos)?;
+                let timeout_ms = read_u64(&raw, &mut pos)?;
+                Request::Watch(key, timeout_ms)
+            },
+            WATCH_RANGE => {
+                let key1 = Key::read_from(&raw, &mut pos)?;
+                let key2 = Key::read_from(&raw, &mut pos)?;
+                let timeout_ms = read_u64(&raw, &mut pos)?;
+                Request::WatchRange(key1, key2, timeout_ms)
+            },
+            SCAN_PAGE => {
+                let key1 = Key::read_from(&raw, &mut pos)?;
+                let key2 = Key::read_from(&raw, &mut pos)?;
+                let limit = read_u64(&raw, &mut pos)? as usize;
+                let after_token = match raw.get(pos) {
+                    Some(&NIL) => { pos += 1; None },
+                    Some(&PRESENT) => { pos += 1; Some(Key::read_from(&raw, &mut pos)?) },
+                    _ => return Err(ProtocolError::new("incorrect presence byte in scan page request"))
+                };
+                Request::ScanPage(key1, key2, limit, after_token)
+            },
+            RESUME => {
+                let scan_id = read_u64(&raw, &mut pos)?;
+                let last_key = Key::read_from(&raw, &mut pos)?;
+                Request::Resume(scan_id, last_key)
+            },
+            CLOSE => Request::Close,
+            _ => return Err(ProtocolError::new("incorrect response chunk identifier"))
+        };
+        if pos != raw.len() {
+            return Err(ProtocolError::new("trailing bytes after request"));
         }
+        Ok(request)
     }
 }
 
 // Reply format
 // -- 1 byte data kind
 //    'S'
-//    -- VALUE_SIZE value
+//    -- 1 byte presence (0 = nil, 1 = present)
+//    -- value, see `Value`'s `Serializable` impl, only if present
 //    'N'
 //    -- 8 bytes number
 //    'P'
-//    -- multiple KEY_SIZE + VALUE_SIZE key-value pairs
+//    -- any number of key-value pairs, each a `Key` followed by a `Value` (both via their
+//       `Serializable` impls), filling the rest of the chunk
 //    'E'
 //    'A'
+//    'T'
+//    'B'
+//    -- any number of `OpOutcome`s (see its `Serializable` impl), filling the rest of the chunk
+//    'W'
+//    -- 1 byte presence (0 = nil, 1 = present)
+//    -- if present: KEY_SIZE key, then a value (presence byte + `Value`, as in 'S' above), then 8
+//       bytes token
+//    'X'
+//    -- 1 byte presence (0 = nil, 1 = present) of the next-page continuation token
+//    -- KEY_SIZE next_token, only if present
+//    -- any number of key-value pairs, filling the rest of the chunk
+//    'I'
+//    -- 8 bytes scan_id, see `Request::Resume`
 
 const SINGLE_VALUE: u8 = b'S';
 const NUMBER: u8 = b'N';
 const KV_PAIRS: u8 = b'P';
 const ERROR: u8 = b'E';
 const SUCCESS: u8 = b'A';
+const SCAN_END: u8 = b'T';
+const BATCH_RESULT: u8 = b'B';
+const WATCH_EVENT: u8 = b'W';
+const PAGE: u8 = b'X';
+const SCAN_STARTED: u8 = b'I';
+
+const PRESENT: u8 = 1;
+const NIL: u8 = 0;
+
+/// The outcome of one `Op` within a `Request::Batch`, as produced by the server. Reuses the
+/// `SINGLE_VALUE`/`NUMBER`/`SUCCESS` reply tags and the `PRESENT`/`NIL` presence byte so the wire
+/// format of a single outcome matches what the equivalent standalone reply would have looked like
+pub enum OpOutcome {
+    /// The result of a `Op::Get`
+    Value(Option<Arc<Value>>),
+    /// The result of a `Op::Del`, the number of rows effected
+    Deleted(usize),
+    /// The result of a `Op::Put`
+    Put
+}
+
+impl Serializable for OpOutcome {
+    fn write_to(&self, buf: &mut Vec<u8>) {
+        match self {
+            OpOutcome::Value(value) => {
+                buf.push(SINGLE_VALUE);
+                match value {
+                    Some(value) => {
+                        buf.push(PRESENT);
+                        value.write_to(buf);
+                    },
+                    None => buf.push(NIL)
+                }
+            },
+            OpOutcome::Deleted(rows_effected) => {
+                buf.push(NUMBER);
+                let mut n = *rows_effected;
+                let mut arr = [0u8; 8];
+                for i in (0..8).rev() {
+                    arr[i] = (n % 256) as u8;
+                    n /= 256;
+                }
+                buf.extend_from_slice(&arr);
+            },
+            OpOutcome::Put => {
+                buf.push(SUCCESS);
+            }
+        }
+    }
+}
+
+/// The owned counterpart to `OpOutcome`, as decoded by the client from a `ReplyChunk::BatchResult`
+pub enum ClientOpOutcome {
+    Value(Option<Value>),
+    Deleted(usize),
+    Put
+}
+
+impl Deserializable for ClientOpOutcome {
+    fn read_from(buf: &[u8], pos: &mut usize) -> Result<Self, KVStorageError> {
+        let kind = *buf.get(*pos).ok_or_else(|| KVStorageError::new("truncated batch result"))?;
+        *pos += 1;
+        match kind {
+            SINGLE_VALUE => {
+                match buf.get(*pos) {
+                    Some(&NIL) => {
+                        *pos += 1;
+                        Ok(ClientOpOutcome::Value(None))
+                    },
+                    Some(&PRESENT) => {
+                        *pos += 1;
+                        Ok(ClientOpOutcome::Value(Some(Value::read_from(buf, pos)?)))
+                    },
+                    _ => Err(KVStorageError::new("incorrect presence byte in batch result"))
+                }
+            },
+            NUMBER => {
+                if *pos + KEY_SIZE > buf.len() {
+                    return Err(KVStorageError::new("truncated batch result"));
+                }
+                let mut ret = 0;
+                for &byte in buf[*pos..*pos + KEY_SIZE].iter() {
+                    ret *= 256;
+                    ret += byte as usize;
+                }
+                *pos += KEY_SIZE;
+                Ok(ClientOpOutcome::Deleted(ret))
+            },
+            SUCCESS => Ok(ClientOpOutcome::Put),
+            _ => Err(KVStorageError::new("incorrect op outcome identifier"))
+        }
+    }
+}
 
 /// A reply chunk sent by server, see its enumerators for further information
 ///
@@ -175,6 +447,20 @@ pub enum ServerReplyChunk<'a> {
     SingleValue(Option<Arc<Value>>),
     Number(usize),
     KVPairs(&'a [(Key, Arc<Value>)]),
+    /// Precedes every streamed `Scan` reply, carrying the `scan_id` a client can later pass to
+    /// `Request::Resume` if the connection drops mid-stream -- see `kvserver::resync::ScanResumeRegistry`
+    ScanStarted(u64),
+    /// Terminates a streamed `Scan` reply: one or more `KVPairs` chunks followed by this marker,
+    /// see `KVStorage::scan_chunked` and `ScanAccumulator`
+    ScanEnd,
+    /// The per-op outcomes of a `Request::Batch`, in the same order as the batch's `Op`s
+    BatchResult(Vec<OpOutcome>),
+    /// The reply to a `Request::Watch`/`WatchRange`; `None` if the request timed out before any
+    /// matching key changed
+    Watch(Option<WatchEvent>),
+    /// The reply to a `Request::ScanPage`: at most `limit` pairs, and the continuation token to
+    /// pass as `after_token` on the next page (`None` once the range is exhausted)
+    Page(&'a [(Key, Arc<Value>)], Option<Key>),
     Error,
     Success
 }
@@ -185,8 +471,12 @@ impl ServerReplyChunk<'_> {
         match self {
             ServerReplyChunk::SingleValue(value) => {
                 let mut ret = vec![SINGLE_VALUE];
-                if let Some(value) = value {
-                    ret.append(&mut value.serialize());
+                match value {
+                    Some(value) => {
+                        ret.push(PRESENT);
+                        value.write_to(&mut ret);
+                    },
+                    None => ret.push(NIL)
                 }
                 ret
             },
@@ -204,8 +494,57 @@ impl ServerReplyChunk<'_> {
             ServerReplyChunk::KVPairs(pairs) => {
                 let mut ret = vec![KV_PAIRS];
                 for (key, value) in pairs.iter() {
-                    ret.append(&mut key.serialize());
-                    ret.append(&mut value.serialize());
+                    key.write_to(&mut ret);
+                    value.write_to(&mut ret);
+                }
+                ret
+            },
+            ServerReplyChunk::ScanStarted(scan_id) => {
+                let mut ret = vec![SCAN_STARTED];
+                write_u64(&mut ret, *scan_id);
+                ret
+            },
+            ServerReplyChunk::ScanEnd => {
+                vec![SCAN_END]
+            },
+            ServerReplyChunk::BatchResult(outcomes) => {
+                let mut ret = vec![BATCH_RESULT];
+                for outcome in outcomes {
+                    outcome.write_to(&mut ret);
+                }
+                ret
+            },
+            ServerReplyChunk::Watch(event) => {
+                let mut ret = vec![WATCH_EVENT];
+                match event {
+                    Some(event) => {
+                        ret.push(PRESENT);
+                        event.key.write_to(&mut ret);
+                        match &event.value {
+                            Some(value) => {
+                                ret.push(PRESENT);
+                                value.write_to(&mut ret);
+                            },
+                            None => ret.push(NIL)
+                        }
+                        write_u64(&mut ret, event.token);
+                    },
+                    None => ret.push(NIL)
+                }
+                ret
+            },
+            ServerReplyChunk::Page(pairs, next_token) => {
+                let mut ret = vec![PAGE];
+                match next_token {
+                    Some(next_token) => {
+                        ret.push(PRESENT);
+                        next_token.write_to(&mut ret);
+                    },
+                    None => ret.push(NIL)
+                }
+                for (key, value) in pairs.iter() {
+                    key.write_to(&mut ret);
+                    value.write_to(&mut ret);
                 }
                 ret
             },
@@ -219,6 +558,14 @@ impl ServerReplyChunk<'_> {
     }
 }
 
+/// The owned counterpart to `WatchEvent`, as decoded by the client from a `ReplyChunk::Watch`
+pub struct ClientWatchEvent {
+    pub key: Key,
+    /// `None` if the change was a delete
+    pub value: Option<Value>,
+    pub token: u64
+}
+
 /// A reply chunk received by client, see its enumerators for further information
 ///
 /// The `ReplyChunk` is specially created by client side program to deserialize and resolve reply
@@ -227,6 +574,17 @@ pub enum ReplyChunk {
     SingleValue(Option<Value>),
     Number(usize),
     KVPairs(Vec<(Key, Value)>),
+    /// Precedes every streamed `Scan` reply, see `ServerReplyChunk::ScanStarted`
+    ScanStarted(u64),
+    /// Terminates a streamed `Scan` reply, see `ScanAccumulator`
+    ScanEnd,
+    /// The per-op outcomes of a `Request::Batch`, in the same order as the batch's `Op`s
+    BatchResult(Vec<ClientOpOutcome>),
+    /// The reply to a `Request::Watch`/`WatchRange`; `None` if the request timed out before any
+    /// matching key changed
+    Watch(Option<ClientWatchEvent>),
+    /// The reply to a `Request::ScanPage`, see `ServerReplyChunk::Page`
+    Page(Vec<(Key, Value)>, Option<Key>),
     Success,
     Error
 }
@@ -240,13 +598,17 @@ impl ReplyChunk {
         assert!(!raw.is_empty());
         match raw[0] {
             SINGLE_VALUE => {
-                if raw.len() == 1 {
-                  Ok(ReplyChunk::SingleValue(None))
-                } else if raw.len() == 1 + VALUE_SIZE {
-                    let ret = Value::from_slice(&raw[1..1+VALUE_SIZE]);
-                    Ok(ReplyChunk::SingleValue(Some(ret)))
-                } else {
-                    Err(ProtocolError::new("incorrect content length"))
+                match raw.get(1) {
+                    Some(&NIL) if raw.len() == 2 => Ok(ReplyChunk::SingleValue(None)),
+                    Some(&PRESENT) => {
+                        let mut pos = 2;
+                        let value = Value::read_from(&raw, &mut pos)?;
+                        if pos != raw.len() {
+                            return Err(ProtocolError::new("trailing bytes after single value"));
+                        }
+                        Ok(ReplyChunk::SingleValue(Some(value)))
+                    },
+                    _ => Err(ProtocolError::new("incorrect content length"))
                 }
             },
             NUMBER => {
@@ -262,18 +624,77 @@ impl ReplyChunk {
                 }
             },
             KV_PAIRS => {
-                if (raw.len() - 1) % (KEY_SIZE + VALUE_SIZE) != 0 {
-                    return Err(ProtocolError::new("incorrect content length"))
-                } else {
-                    let mut ret = Vec::new();
-                    for i in (1..raw.len()).step_by(KEY_SIZE + VALUE_SIZE) {
-                        let key = Key::from_slice(&raw[i..i + KEY_SIZE]);
-                        let value = Value::from_slice(&raw[i+KEY_SIZE..i+KEY_SIZE+VALUE_SIZE]);
-                        ret.push((key, value))
-                    }
-                    Ok(ReplyChunk::KVPairs(ret))
+                let mut pos = 1;
+                let mut ret = Vec::new();
+                while pos < raw.len() {
+                    let key = Key::read_from(&raw, &mut pos)?;
+                    let value = Value::read_from(&raw, &mut pos)?;
+                    ret.push((key, value));
                 }
+                Ok(ReplyChunk::KVPairs(ret))
             },
+            SCAN_STARTED => {
+                let mut pos = 1;
+                let scan_id = read_u64(&raw, &mut pos)?;
+                if pos != raw.len() {
+                    return Err(ProtocolError::new("trailing bytes after scan started"));
+                }
+                Ok(ReplyChunk::ScanStarted(scan_id))
+            }
+            SCAN_END => {
+                if raw.len() != 1 {
+                    Err(ProtocolError::new("incorrect content length"))
+                } else {
+                    Ok(ReplyChunk::ScanEnd)
+                }
+            }
+            BATCH_RESULT => {
+                let mut pos = 1;
+                let mut ret = Vec::new();
+                while pos < raw.len() {
+                    ret.push(ClientOpOutcome::read_from(&raw, &mut pos)?);
+                }
+                Ok(ReplyChunk::BatchResult(ret))
+            }
+            WATCH_EVENT => {
+                match raw.get(1) {
+                    Some(&NIL) if raw.len() == 2 => Ok(ReplyChunk::Watch(None)),
+                    Some(&PRESENT) => {
+                        let mut pos = 2;
+                        let key = Key::read_from(&raw, &mut pos)?;
+                        let value = match raw.get(pos) {
+                            Some(&NIL) => { pos += 1; None },
+                            Some(&PRESENT) => { pos += 1; Some(Value::read_from(&raw, &mut pos)?) },
+                            _ => return Err(ProtocolError::new("incorrect presence byte in watch event"))
+                        };
+                        let token = read_u64(&raw, &mut pos)?;
+                        if pos != raw.len() {
+                            return Err(ProtocolError::new("trailing bytes after watch event"));
+                        }
+                        Ok(ReplyChunk::Watch(Some(ClientWatchEvent { key, value, token })))
+                    },
+                    _ => Err(ProtocolError::new("incorrect content length"))
+                }
+            }
+            PAGE => {
+                let next_token = match raw.get(1) {
+                    Some(&NIL) => None,
+                    Some(&PRESENT) => {
+                        let mut pos = 2;
+                        let token = Key::read_from(&raw, &mut pos)?;
+                        Some(token)
+                    },
+                    _ => return Err(ProtocolError::new("incorrect presence byte in page"))
+                };
+                let mut pos = if next_token.is_some() { 2 + KEY_SIZE } else { 2 };
+                let mut pairs = Vec::new();
+                while pos < raw.len() {
+                    let key = Key::read_from(&raw, &mut pos)?;
+                    let value = Value::read_from(&raw, &mut pos)?;
+                    pairs.push((key, value));
+                }
+                Ok(ReplyChunk::Page(pairs, next_token))
+            }
             SUCCESS => {
                 if raw.len() != 1 {
                     Err(ProtocolError::new("incorrect content length"))
@@ -295,6 +716,48 @@ impl ReplyChunk {
     }
 }
 
+/// Accumulates a stream of `KVPairs` reply chunks (see `KVStorage::scan_chunked`) into one logical
+/// scan result, recognizing the `ScanEnd` terminator so a caller reading chunks off a transport one
+/// at a time knows when the stream is complete
+#[derive(Default)]
+pub struct ScanAccumulator {
+    pairs: Vec<(Key, Value)>,
+    done: bool
+}
+
+impl ScanAccumulator {
+    /// Creates an empty accumulator
+    pub fn new() -> Self {
+        ScanAccumulator { pairs: Vec::new(), done: false }
+    }
+
+    /// Feeds one raw reply chunk into the accumulator. Returns `Ok(true)` once the `ScanEnd`
+    /// terminator has been seen, at which point no further chunks should be pushed; returns
+    /// `Ok(false)` if more chunks are still expected.
+    pub fn push(&mut self, raw: Vec<u8>) -> Result<bool, ProtocolError> {
+        match ReplyChunk::deserialize(raw)? {
+            ReplyChunk::KVPairs(mut pairs) => {
+                self.pairs.append(&mut pairs);
+                Ok(false)
+            },
+            ReplyChunk::ScanEnd => {
+                self.done = true;
+                Ok(true)
+            },
+            _ => Err(ProtocolError::new("unexpected reply chunk kind in scan stream"))
+        }
+    }
+
+    /// Consumes the accumulator, returning every pair accumulated so far.
+    ///
+    /// Panics if `push` has not yet returned `Ok(true)`, since the stream may still have pairs in
+    /// flight.
+    pub fn into_pairs(self) -> Vec<(Key, Value)> {
+        assert!(self.done, "scan stream has not been terminated yet");
+        self.pairs
+    }
+}
+
 #[cfg(test)]
 mod test_request {
     use crate::kvserver::protocol::Request;
@@ -364,6 +827,131 @@ mod test_request {
         }
     }
 
+    #[test]
+    fn request_serialize_batch() {
+        use crate::kvserver::protocol::Op;
+
+        for _ in 1..10 {
+            let put_key = gen_key();
+            let put_value = gen_value();
+            let get_key = gen_key();
+            let del_key = gen_key();
+            let req = Request::Batch(vec![
+                Op::Put(put_key, put_value),
+                Op::Get(get_key),
+                Op::Del(del_key)
+            ]);
+            let req1 = Request::deserialize_from(req.serialize()).unwrap();
+            match req1 {
+                Request::Batch(ops) => {
+                    assert_eq!(ops.len(), 3);
+                    match &ops[0] {
+                        Op::Put(k, v) => { assert_eq!(k, &put_key); assert_eq!(v, &put_value); },
+                        _ => panic!()
+                    }
+                    match &ops[1] {
+                        Op::Get(k) => assert_eq!(k, &get_key),
+                        _ => panic!()
+                    }
+                    match &ops[2] {
+                        Op::Del(k) => assert_eq!(k, &del_key),
+                        _ => panic!()
+                    }
+                },
+                _ => panic!()
+            }
+        }
+    }
+
+    #[test]
+    fn request_serialize_watch() {
+        for _ in 1..10 {
+            let key = gen_key();
+            let req = Request::Watch(key, 1234);
+            let req1 = Request::deserialize_from(req.serialize()).unwrap();
+            match req1 {
+                Request::Watch(k, timeout_ms) => {
+                    assert_eq!(k, key);
+                    assert_eq!(timeout_ms, 1234);
+                },
+                _ => panic!()
+            }
+        }
+    }
+
+    #[test]
+    fn request_serialize_watch_range() {
+        for _ in 1..10 {
+            let key1 = gen_key();
+            let key2 = gen_key();
+            let req = Request::WatchRange(key1, key2, 5678);
+            let req1 = Request::deserialize_from(req.serialize()).unwrap();
+            match req1 {
+                Request::WatchRange(k1, k2, timeout_ms) => {
+                    assert_eq!(k1, key1);
+                    assert_eq!(k2, key2);
+                    assert_eq!(timeout_ms, 5678);
+                },
+                _ => panic!()
+            }
+        }
+    }
+
+    #[test]
+    fn request_serialize_scan_page() {
+        for _ in 1..10 {
+            let key1 = gen_key();
+            let key2 = gen_key();
+            let req = Request::ScanPage(key1, key2, 100, None);
+            let req1 = Request::deserialize_from(req.serialize()).unwrap();
+            match req1 {
+                Request::ScanPage(k1, k2, limit, after_token) => {
+                    assert_eq!(k1, key1);
+                    assert_eq!(k2, key2);
+                    assert_eq!(limit, 100);
+                    assert_eq!(after_token, None);
+                },
+                _ => panic!()
+            }
+        }
+    }
+
+    #[test]
+    fn request_serialize_scan_page_with_after_token() {
+        for _ in 1..10 {
+            let key1 = gen_key();
+            let key2 = gen_key();
+            let after_token = gen_key();
+            let req = Request::ScanPage(key1, key2, 50, Some(after_token));
+            let req1 = Request::deserialize_from(req.serialize()).unwrap();
+            match req1 {
+                Request::ScanPage(k1, k2, limit, after_token_result) => {
+                    assert_eq!(k1, key1);
+                    assert_eq!(k2, key2);
+                    assert_eq!(limit, 50);
+                    assert_eq!(after_token_result, Some(after_token));
+                },
+                _ => panic!()
+            }
+        }
+    }
+
+    #[test]
+    fn request_serialize_resume() {
+        for _ in 1..10 {
+            let last_key = gen_key();
+            let req = Request::Resume(42, last_key);
+            let req1 = Request::deserialize_from(req.serialize()).unwrap();
+            match req1 {
+                Request::Resume(scan_id, k) => {
+                    assert_eq!(scan_id, 42);
+                    assert_eq!(k, last_key);
+                },
+                _ => panic!()
+            }
+        }
+    }
+
     #[test]
     fn request_serialize_close() {
         for _ in 1..10 {
@@ -437,4 +1025,146 @@ mod test_reply_chunk {
             }
         }
     }
+
+    #[test]
+    fn reply_serialize_scan_started() {
+        let chunk = ReplyChunk::deserialize(ServerReplyChunk::ScanStarted(7).serialize()).unwrap();
+        match chunk {
+            ReplyChunk::ScanStarted(scan_id) => assert_eq!(scan_id, 7),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn reply_serialize_scan_end() {
+        let chunk = ReplyChunk::deserialize(ServerReplyChunk::ScanEnd.serialize()).unwrap();
+        match chunk {
+            ReplyChunk::ScanEnd => (),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn reply_serialize_batch_result() {
+        use crate::kvserver::protocol::{ClientOpOutcome, OpOutcome};
+
+        let value = Arc::new(gen_value());
+        let outcomes = vec![
+            OpOutcome::Put,
+            OpOutcome::Value(Some(value.clone())),
+            OpOutcome::Value(None),
+            OpOutcome::Deleted(1)
+        ];
+        let chunk = ReplyChunk::deserialize(ServerReplyChunk::BatchResult(outcomes).serialize()).unwrap();
+        match chunk {
+            ReplyChunk::BatchResult(outcomes) => {
+                assert_eq!(outcomes.len(), 4);
+                match &outcomes[0] {
+                    ClientOpOutcome::Put => (),
+                    _ => panic!()
+                }
+                match &outcomes[1] {
+                    ClientOpOutcome::Value(Some(v)) => assert_eq!(v, value.deref()),
+                    _ => panic!()
+                }
+                match &outcomes[2] {
+                    ClientOpOutcome::Value(None) => (),
+                    _ => panic!()
+                }
+                match &outcomes[3] {
+                    ClientOpOutcome::Deleted(n) => assert_eq!(*n, 1),
+                    _ => panic!()
+                }
+            },
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn reply_serialize_watch_present() {
+        use crate::kvserver::watch::WatchEvent;
+
+        let key = gen_key();
+        let value = Arc::new(gen_value());
+        let event = WatchEvent { key, value: Some(value.clone()), token: 42 };
+        let chunk = ReplyChunk::deserialize(ServerReplyChunk::Watch(Some(event)).serialize()).unwrap();
+        match chunk {
+            ReplyChunk::Watch(Some(event)) => {
+                assert_eq!(event.key, key);
+                assert_eq!(event.value.unwrap(), *value);
+                assert_eq!(event.token, 42);
+            },
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn reply_serialize_watch_timed_out() {
+        let chunk = ReplyChunk::deserialize(ServerReplyChunk::Watch(None).serialize()).unwrap();
+        match chunk {
+            ReplyChunk::Watch(None) => (),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn reply_serialize_page_with_next_token() {
+        let pairs = vec![(gen_key(), Arc::new(gen_value())), (gen_key(), Arc::new(gen_value()))];
+        let next_token = gen_key();
+        let chunk = ReplyChunk::deserialize(ServerReplyChunk::Page(&pairs, Some(next_token)).serialize()).unwrap();
+        match chunk {
+            ReplyChunk::Page(ps, token) => {
+                assert_eq!(ps.len(), pairs.len());
+                for ((k1, v1), (k2, v2)) in ps.iter().zip(pairs.iter()) {
+                    assert_eq!(k1, k2);
+                    assert_eq!(v1, v2.deref());
+                }
+                assert_eq!(token, Some(next_token));
+            },
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn reply_serialize_page_exhausted() {
+        let pairs = vec![(gen_key(), Arc::new(gen_value()))];
+        let chunk = ReplyChunk::deserialize(ServerReplyChunk::Page(&pairs, None).serialize()).unwrap();
+        match chunk {
+            ReplyChunk::Page(ps, token) => {
+                assert_eq!(ps.len(), 1);
+                assert_eq!(token, None);
+            },
+            _ => panic!()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_scan_accumulator {
+    use crate::kvserver::protocol::{ScanAccumulator, ServerReplyChunk};
+    use crate::util::{gen_key, gen_value};
+    use std::sync::Arc;
+
+    #[test]
+    fn accumulates_pairs_across_chunks_until_the_terminator() {
+        let first_pairs = vec![(gen_key(), Arc::new(gen_value())), (gen_key(), Arc::new(gen_value()))];
+        let second_pairs = vec![(gen_key(), Arc::new(gen_value()))];
+
+        let mut acc = ScanAccumulator::new();
+        assert_eq!(acc.push(ServerReplyChunk::KVPairs(&first_pairs).serialize()).unwrap(), false);
+        assert_eq!(acc.push(ServerReplyChunk::KVPairs(&second_pairs).serialize()).unwrap(), false);
+        assert_eq!(acc.push(ServerReplyChunk::ScanEnd.serialize()).unwrap(), true);
+
+        let pairs = acc.into_pairs();
+        assert_eq!(pairs.len(), 3);
+        assert_eq!(pairs[0].0, first_pairs[0].0);
+        assert_eq!(pairs[2].0, second_pairs[0].0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn into_pairs_panics_before_termination() {
+        let acc = ScanAccumulator::new();
+        acc.into_pairs();
+    }
 }
@@ -0,0 +1,296 @@
+//! Redis Serialization Protocol (RESP) front-end for Project-KV
+//!
+//! This module lets off-the-shelf redis clients and tooling (e.g. `redis-cli`) talk to a
+//! Project-KV server directly, without going through chunktp framing, mirroring how TinKV exposes
+//! a redis-compatible server. It only understands the subset of RESP needed to carry
+//! `GET`/`SET`/`DEL`/`SCAN`: simple strings (`+OK\r\n`), errors (`-ERR msg\r\n`), integers
+//! (`:123\r\n`), bulk strings (`$5\r\nhello\r\n`, with `$-1\r\n` meaning nil) and arrays
+//! (`*N\r\n` followed by N elements).
+
+use crate::kvserver::protocol::Request;
+use crate::kvstorage::{Key, Value};
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, Read, Write};
+use std::sync::Arc;
+
+/// The error type used by the resp module
+#[derive(Debug)]
+pub struct RespError {
+    description: String
+}
+
+impl RespError {
+    pub fn new(description: &str) -> Self {
+        RespError { description: description.to_owned() }
+    }
+}
+
+impl Display for RespError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(f, "resp error: {}", self.description)
+    }
+}
+
+impl Error for RespError {
+}
+
+/// Largest bulk string a peer may declare in a `$<len>` header, before we allocate a buffer for
+/// it. Keeps a malicious or buggy client from driving an out-of-memory abort with e.g.
+/// `$999999999999\r\n`.
+const MAX_BULK_LEN: i64 = 512 * 1024 * 1024;
+
+/// Largest array a peer may declare in a `*<len>` header, before we reserve capacity for it.
+const MAX_ARRAY_LEN: i64 = 1024 * 1024;
+
+/// A deserialized RESP value, as read back from a server or client
+pub enum RespValue {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<RespValue>>)
+}
+
+fn read_line<R: BufRead>(reader: &mut R) -> Result<String, Box<dyn Error>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    while line.ends_with('\n') || line.ends_with('\r') {
+        line.pop();
+    }
+    Ok(line)
+}
+
+/// Read and parse one RESP value from `reader`
+pub fn read_value<R: BufRead>(reader: &mut R) -> Result<RespValue, Box<dyn Error>> {
+    let header = read_line(reader)?;
+    if header.is_empty() {
+        return Err(Box::new(RespError::new("empty reply header")));
+    }
+    let (kind, rest) = header.split_at(1);
+    match kind.as_bytes()[0] {
+        b'+' => Ok(RespValue::Simple(rest.to_owned())),
+        b'-' => Ok(RespValue::Error(rest.to_owned())),
+        b':' => {
+            let n = rest.parse().map_err(|_| RespError::new("invalid integer reply"))?;
+            Ok(RespValue::Integer(n))
+        },
+        b'$' => {
+            let len: i64 = rest.parse().map_err(|_| RespError::new("invalid bulk length"))?;
+            if len < 0 {
+                return Ok(RespValue::Bulk(None));
+            }
+            if len > MAX_BULK_LEN {
+                return Err(Box::new(RespError::new(&format!(
+                    "bulk length {} exceeds MAX_BULK_LEN ({} bytes)", len, MAX_BULK_LEN
+                ))));
+            }
+            let mut buf = vec![0u8; len as usize];
+            reader.read_exact(&mut buf)?;
+            let mut crlf = [0u8; 2];
+            reader.read_exact(&mut crlf)?;
+            Ok(RespValue::Bulk(Some(buf)))
+        },
+        b'*' => {
+            let len: i64 = rest.parse().map_err(|_| RespError::new("invalid array length"))?;
+            if len < 0 {
+                return Ok(RespValue::Array(None));
+            }
+            if len > MAX_ARRAY_LEN {
+                return Err(Box::new(RespError::new(&format!(
+                    "array length {} exceeds MAX_ARRAY_LEN ({} elements)", len, MAX_ARRAY_LEN
+                ))));
+            }
+            let mut items = Vec::with_capacity(len as usize);
+            for _ in 0..len {
+                items.push(read_value(reader)?);
+            }
+            Ok(RespValue::Array(Some(items)))
+        },
+        _ => Err(Box::new(RespError::new("unrecognized RESP type byte")))
+    }
+}
+
+/// Read one inbound RESP command (an array of bulk strings) from `reader` and convert it into a
+/// `Request`. Returns `Ok(None)` if the stream closed cleanly before any data arrived.
+pub fn read_request<R: BufRead>(reader: &mut R) -> Result<Option<Request>, Box<dyn Error>> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let parts = match read_value(reader)? {
+        RespValue::Array(Some(items)) => {
+            let mut parts = Vec::with_capacity(items.len());
+            for item in items {
+                match item {
+                    RespValue::Bulk(Some(data)) => parts.push(data),
+                    _ => return Err(Box::new(RespError::new("command arguments must be bulk strings")))
+                }
+            }
+            parts
+        },
+        _ => return Err(Box::new(RespError::new("expected a command array")))
+    };
+    if parts.is_empty() {
+        return Err(Box::new(RespError::new("empty command array")));
+    }
+
+    let request = match parts[0].to_ascii_uppercase().as_slice() {
+        b"GET" => {
+            if parts.len() != 2 {
+                return Err(Box::new(RespError::new("GET requires exactly 1 argument")));
+            }
+            Request::Get(parse_key(&parts[1])?)
+        },
+        b"SET" => {
+            if parts.len() != 3 {
+                return Err(Box::new(RespError::new("SET requires exactly 2 arguments")));
+            }
+            Request::Put(parse_key(&parts[1])?, parse_value(&parts[2])?)
+        },
+        b"DEL" => {
+            if parts.len() != 2 {
+                return Err(Box::new(RespError::new("DEL requires exactly 1 argument")));
+            }
+            Request::Del(parse_key(&parts[1])?)
+        },
+        b"SCAN" => {
+            if parts.len() != 3 {
+                return Err(Box::new(RespError::new("SCAN requires exactly 2 arguments")));
+            }
+            Request::Scan(parse_key(&parts[1])?, parse_key(&parts[2])?)
+        },
+        b"QUIT" => Request::Close,
+        other => {
+            return Err(Box::new(RespError::new(
+                &format!("unsupported command '{}'", String::from_utf8_lossy(other)))))
+        }
+    };
+    Ok(Some(request))
+}
+
+fn parse_key(raw: &[u8]) -> Result<Key, RespError> {
+    Key::from_slice_checked(raw).ok_or_else(|| RespError::new("incorrect key size"))
+}
+
+fn parse_value(raw: &[u8]) -> Result<Value, RespError> {
+    Value::from_slice_checked(raw).ok_or_else(|| RespError::new("incorrect value size"))
+}
+
+/// Encode a nil bulk string reply (`$-1\r\n`)
+pub fn encode_nil() -> Vec<u8> {
+    b"$-1\r\n".to_vec()
+}
+
+/// Encode a bulk string reply
+pub fn encode_bulk_string(data: &[u8]) -> Vec<u8> {
+    let mut ret = format!("${}\r\n", data.len()).into_bytes();
+    ret.extend_from_slice(data);
+    ret.extend_from_slice(b"\r\n");
+    ret
+}
+
+/// Encode a simple string reply, e.g. `+OK\r\n`
+pub fn encode_simple_string(s: &str) -> Vec<u8> {
+    format!("+{}\r\n", s).into_bytes()
+}
+
+/// Encode an error reply, e.g. `-ERR msg\r\n`
+pub fn encode_error(msg: &str) -> Vec<u8> {
+    format!("-ERR {}\r\n", msg).into_bytes()
+}
+
+/// Encode an integer reply, e.g. `:123\r\n`
+pub fn encode_integer(n: i64) -> Vec<u8> {
+    format!(":{}\r\n", n).into_bytes()
+}
+
+/// Encode the `*N\r\n` header of an array reply
+pub fn encode_array_header(n: usize) -> Vec<u8> {
+    format!("*{}\r\n", n).into_bytes()
+}
+
+/// Encode a full command (or any bulk-string array) ready to be written to the wire
+pub fn encode_command(parts: &[&[u8]]) -> Vec<u8> {
+    let mut ret = encode_array_header(parts.len());
+    for part in parts {
+        ret.extend_from_slice(&encode_bulk_string(part));
+    }
+    ret
+}
+
+/// Encode the reply to a `GET`/`do_get` as a RESP bulk string, or nil if the key was absent
+pub fn encode_get_reply(value: Option<Arc<Value>>) -> Vec<u8> {
+    match value {
+        Some(value) => encode_bulk_string(&value.serialize()),
+        None => encode_nil()
+    }
+}
+
+/// Encode the reply to a `SCAN` as a RESP array of `[key, value]` bulk string pairs
+pub fn encode_scan_reply(pairs: &[(Key, Arc<Value>)]) -> Vec<u8> {
+    let mut ret = encode_array_header(pairs.len());
+    for (key, value) in pairs.iter() {
+        ret.extend_from_slice(&encode_array_header(2));
+        ret.extend_from_slice(&encode_bulk_string(&key.serialize()));
+        ret.extend_from_slice(&encode_bulk_string(&value.serialize()));
+    }
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use crate::kvserver::resp::{encode_command, read_request, encode_get_reply, read_value, RespValue};
+    use crate::kvserver::protocol::Request;
+    use crate::util::{gen_key, gen_value};
+    use std::io::BufReader;
+    use std::sync::Arc;
+
+    #[test]
+    fn resp_parses_get() {
+        let key = gen_key();
+        let raw = encode_command(&[b"GET", &key.serialize()]);
+        let mut reader = BufReader::new(raw.as_slice());
+        match read_request(&mut reader).unwrap().unwrap() {
+            Request::Get(k) => assert_eq!(k, key),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn resp_parses_set() {
+        let key = gen_key();
+        let value = gen_value();
+        let raw = encode_command(&[b"SET", &key.serialize(), &value.serialize()]);
+        let mut reader = BufReader::new(raw.as_slice());
+        match read_request(&mut reader).unwrap().unwrap() {
+            Request::Put(k, v) => {
+                assert_eq!(k, key);
+                assert_eq!(v, value);
+            },
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn resp_get_reply_roundtrips() {
+        let value = Arc::new(gen_value());
+        let raw = encode_get_reply(Some(value.clone()));
+        let mut reader = BufReader::new(raw.as_slice());
+        match read_value(&mut reader).unwrap() {
+            RespValue::Bulk(Some(data)) => assert_eq!(data, value.serialize()),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn resp_get_reply_nil() {
+        let raw = encode_get_reply(None);
+        let mut reader = BufReader::new(raw.as_slice());
+        match read_value(&mut reader).unwrap() {
+            RespValue::Bulk(None) => (),
+            _ => panic!()
+        }
+    }
+}
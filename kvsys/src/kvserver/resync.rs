@@ -0,0 +1,74 @@
+//! Lets a dropped connection resume an in-flight `Request::Scan` with `Request::Resume` instead
+//! of restarting the whole range.
+//!
+//! `run_server` keeps one `ScanResumeRegistry` alongside the `KVStorage`, shared across every
+//! connection. `handle_connection`'s `Request::Scan` arm registers a session with `start` and
+//! reports its `scan_id` to the client via `ServerReplyChunk::ScanStarted`, then calls `ack` after
+//! every chunk flushed. A later `Request::Resume` on any connection calls `resume` to look up
+//! where the original stream left off. Sessions expire after `SESSION_TTL` of inactivity so a
+//! client that never reconnects doesn't leak memory.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::kvstorage::Key;
+
+const SESSION_TTL: Duration = Duration::from_secs(60);
+
+struct ScanSession {
+    key2: Key,
+    expires_at: Instant
+}
+
+/// Tracks every in-flight or recently-finished `Request::Scan` stream, keyed by `scan_id`
+#[derive(Default)]
+pub struct ScanResumeRegistry {
+    next_scan_id: Mutex<u64>,
+    sessions: Mutex<HashMap<u64, ScanSession>>
+}
+
+impl ScanResumeRegistry {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        ScanResumeRegistry { next_scan_id: Mutex::new(0), sessions: Mutex::new(HashMap::new()) }
+    }
+
+    /// Registers a new scan over `[_, key2)`, returning the `scan_id` to report to the client
+    pub fn start(&self, key2: Key) -> u64 {
+        self.evict_expired();
+        let scan_id = {
+            let mut next_scan_id = self.next_scan_id.lock().unwrap();
+            *next_scan_id += 1;
+            *next_scan_id
+        };
+        self.sessions.lock().unwrap().insert(scan_id, ScanSession { key2, expires_at: Instant::now() + SESSION_TTL });
+        scan_id
+    }
+
+    /// Refreshes `scan_id`'s TTL after a chunk has been flushed to the client
+    pub fn ack(&self, scan_id: u64) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(&scan_id) {
+            session.expires_at = Instant::now() + SESSION_TTL;
+        }
+    }
+
+    /// Marks `scan_id` as finished, removing its session -- resume is only for a scan that dropped
+    /// mid-stream, not one that already reached `ScanEnd`
+    pub fn finish(&self, scan_id: u64) {
+        self.sessions.lock().unwrap().remove(&scan_id);
+    }
+
+    /// Looks up the upper bound of `scan_id`'s range, so `handle_connection` can resume streaming
+    /// from strictly after the client's `last_key` up to it. Returns `None` if `scan_id` is
+    /// unknown or has expired.
+    pub fn resume(&self, scan_id: u64) -> Option<Key> {
+        self.evict_expired();
+        self.sessions.lock().unwrap().get(&scan_id).map(|session| session.key2)
+    }
+
+    fn evict_expired(&self) {
+        let now = Instant::now();
+        self.sessions.lock().unwrap().retain(|_, session| session.expires_at > now);
+    }
+}
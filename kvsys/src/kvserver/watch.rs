@@ -0,0 +1,113 @@
+//! Long-poll "watch" support for `Request::Watch`/`Request::WatchRange`.
+//!
+//! `run_server` keeps one `WatchRegistry` alongside the `KVStorage`, shared across every
+//! connection. `handle_connection`'s `Request::Put`/`Request::Del` arms call `notify` after every
+//! write; a connection blocked in `Request::Watch`/`WatchRange` calls `wait_key`/`wait_range`,
+//! which parks the handling thread on a `Condvar` until a matching key changes or the request's
+//! `timeout_ms` elapses.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::kvstorage::{Key, Value};
+
+/// One observed change to a key, delivered to a `Request::Watch`/`WatchRange` waiter
+#[derive(Clone)]
+pub struct WatchEvent {
+    pub key: Key,
+    /// `None` if the change was a delete
+    pub value: Option<Arc<Value>>,
+    /// A process-wide monotonically increasing sequence number assigned to every PUT/DEL, so a
+    /// client can tell which of two observed events happened first
+    pub token: u64
+}
+
+enum WatchSpan {
+    Point(Key),
+    Range(Key, Key)
+}
+
+impl WatchSpan {
+    fn contains(&self, key: &Key) -> bool {
+        match self {
+            WatchSpan::Point(watched) => watched == key,
+            WatchSpan::Range(key1, key2) => key.encode() >= key1.encode() && key.encode() < key2.encode()
+        }
+    }
+}
+
+struct Slot {
+    event: Mutex<Option<WatchEvent>>,
+    condvar: Condvar
+}
+
+/// Tracks every connection currently blocked in a `Request::Watch`/`WatchRange`, and wakes the
+/// matching ones whenever a key changes
+#[derive(Default)]
+pub struct WatchRegistry {
+    next_token: Mutex<u64>,
+    waiters: Mutex<Vec<(WatchSpan, Arc<Slot>)>>
+}
+
+impl WatchRegistry {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        WatchRegistry { next_token: Mutex::new(0), waiters: Mutex::new(Vec::new()) }
+    }
+
+    /// Called after every PUT/DEL with the affected key and its new value (`None` for a delete),
+    /// waking every waiter whose watched key or range contains it
+    pub fn notify(&self, key: &Key, value: Option<Arc<Value>>) {
+        let token = {
+            let mut next_token = self.next_token.lock().unwrap();
+            *next_token += 1;
+            *next_token
+        };
+        let event = WatchEvent { key: *key, value, token };
+
+        let waiters = self.waiters.lock().unwrap();
+        for (span, slot) in waiters.iter() {
+            if span.contains(key) {
+                *slot.event.lock().unwrap() = Some(event.clone());
+                slot.condvar.notify_all();
+            }
+        }
+    }
+
+    /// Blocks the calling thread until `key` changes or `timeout` elapses, returning the observed
+    /// `WatchEvent`, or `None` on timeout
+    pub fn wait_key(&self, key: Key, timeout: Duration) -> Option<WatchEvent> {
+        self.wait(WatchSpan::Point(key), timeout)
+    }
+
+    /// Blocks the calling thread until a key in `[key1, key2)` changes or `timeout` elapses,
+    /// returning the observed `WatchEvent`, or `None` on timeout
+    pub fn wait_range(&self, key1: Key, key2: Key, timeout: Duration) -> Option<WatchEvent> {
+        self.wait(WatchSpan::Range(key1, key2), timeout)
+    }
+
+    fn wait(&self, span: WatchSpan, timeout: Duration) -> Option<WatchEvent> {
+        let slot = Arc::new(Slot { event: Mutex::new(None), condvar: Condvar::new() });
+        self.waiters.lock().unwrap().push((span, slot.clone()));
+
+        let deadline = Instant::now() + timeout;
+        let mut event = slot.event.lock().unwrap();
+        let result = loop {
+            if event.is_some() {
+                break event.take();
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break None;
+            }
+            let (guard, timeout_result) = slot.condvar.wait_timeout(event, remaining).unwrap();
+            event = guard;
+            if timeout_result.timed_out() {
+                break event.take();
+            }
+        };
+
+        self.waiters.lock().unwrap().retain(|(_, s)| !Arc::ptr_eq(s, &slot));
+        result
+    }
+}
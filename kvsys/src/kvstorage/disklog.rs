@@ -1,24 +1,34 @@
 //! The Disk Log file API
 
-use crate::kvstorage::{Key, Value, KEY_SIZE, VALUE_SIZE};
+use crate::kvstorage::{Deserializable, Key, Serializable, Value, KEY_SIZE};
 use std::sync::Arc;
 use std::error::Error;
 use std::fs;
-use std::io::{Read, Write};
+use std::collections::HashMap;
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::path::Path;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 
 // Disk log format
-//  -- 1 byte functionality
-//     'P': put
-//      -- KEY_SIZE bytes key
-//      -- VALUE_SIZE bytes value
-//     'D': delete
-//      -- KEY_SIZE bytes key
+//  -- 5 bytes magic b"KVLOG" (omitted in headerless legacy files, see `read_header`)
+//  -- 2 bytes format version (big endian u16)
+//  -- 2 bytes key size (big endian u16, must match KEY_SIZE)
+//  -- any number of records:
+//     -- 1 byte functionality
+//        'P': put
+//         -- KEY_SIZE bytes key
+//         -- value, see `Value`'s `Serializable` impl (VarInt byte-length then the raw bytes)
+//        'D': delete
+//         -- KEY_SIZE bytes key
 
 const DISK_PUT: u8 = b'P';
 const DISK_DELETE: u8 = b'D';
 
+const LOG_MAGIC: &[u8; 5] = b"KVLOG";
+/// Bumped to 2 when `Value` became variable-length and the header dropped its fixed value size
+const LOG_FORMAT_VERSION: u16 = 2;
+
 /// The error type used by disklog module
 #[derive(Debug)]
 pub struct DiskLogError {
@@ -52,17 +62,45 @@ impl DiskLogMessage {
         match self {
             DiskLogMessage::Put(key, value) => {
                 let mut ret = vec![DISK_PUT];
-                ret.append(&mut key.serialize());
-                ret.append(&mut value.serialize());
+                key.write_to(&mut ret);
+                value.write_to(&mut ret);
                 ret
             },
             DiskLogMessage::Delete(key) => {
                 let mut ret = vec![DISK_DELETE];
-                ret.append(&mut key.serialize());
+                key.write_to(&mut ret);
                 ret
             }
         }
     }
+
+    /// Deserialize a `DiskLogMessage` out of an already fully-buffered byte slice -- the
+    /// complement to `serialize`, for callers (see `crypto::EncryptedLogReader`) that decrypt a
+    /// whole record into memory before they have anything resembling a byte stream to read from
+    pub fn deserialize(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        if data.is_empty() {
+            return Err(Box::new(DiskLogError::new("empty disk log record")));
+        }
+        let mut pos = 1;
+        match data[0] {
+            DISK_PUT => {
+                let key = Key::read_from(data, &mut pos)?;
+                let value = Value::read_from(data, &mut pos)?;
+                if pos != data.len() {
+                    return Err(Box::new(DiskLogError::new("trailing bytes after put record")));
+                }
+                Ok(DiskLogMessage::Put(key, Arc::new(value)))
+            },
+            DISK_DELETE => {
+                let key = Key::read_from(data, &mut pos)?;
+                if pos != data.len() {
+                    return Err(Box::new(DiskLogError::new("trailing bytes after delete record")));
+                }
+                Ok(DiskLogMessage::Delete(key))
+            },
+            _ => Err(Box::new(DiskLogError::new("incorrect disk log format")))
+        }
+    }
 }
 
 /// Reader for `DiskLogMessage`
@@ -80,8 +118,14 @@ impl DiskLogReader {
     ///
     /// This function requires the given `File` to be opened with `read`, and the file pointer must
     /// be at the beginning of the file. If not, further operations may return Error
-    pub fn new(disk_log_file: fs::File) -> Self {
-        DiskLogReader { disk_log_file }
+    ///
+    /// The file's header is validated against this build's magic, format version and `KEY_SIZE`.
+    /// Headerless legacy log files (written before the header existed) are also accepted for
+    /// compatibility: if the first bytes don't match the magic, the file pointer is rewound to the
+    /// start and records are read from there, exactly as before the header existed.
+    pub fn new(mut disk_log_file: fs::File) -> Result<Self, Box<dyn Error>> {
+        read_header(&mut disk_log_file)?;
+        Ok(DiskLogReader { disk_log_file })
     }
 
     /// Try reading a log out of the file
@@ -96,7 +140,8 @@ impl DiskLogReader {
                 self.disk_log_file.read_exact(&mut key)?;
                 let key = Key::from_slice(&key);
                 if operate[0] == DISK_PUT {
-                    let mut value = [0u8; VALUE_SIZE];
+                    let value_len = read_stream_varint(&mut self.disk_log_file)? as usize;
+                    let mut value = vec![0u8; value_len];
                     self.disk_log_file.read_exact(&mut value)?;
                     let value = Value::from_slice(&value);
                     Ok(Some(DiskLogMessage::Put(key, Arc::new(value))))
@@ -117,13 +162,35 @@ impl DiskLogReader {
     }
 }
 
+/// Reads a LEB128-style VarInt one byte at a time off `file`, the streaming counterpart to
+/// `Value`'s buffer-based `Deserializable` impl used once a whole record is already in memory
+fn read_stream_varint(file: &mut fs::File) -> Result<u32, Box<dyn Error>> {
+    let mut value: u32 = 0;
+    for i in 0..5 {
+        let mut byte = [0u8; 1];
+        file.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7F) as u32) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Box::new(DiskLogError::new("varint length prefix longer than 5 bytes")))
+}
+
 impl DiskLogWriter {
     /// Create a `DiskLogReader` with given `File`
     ///
     /// This function requires the given `File` to be opened with `write` + `append`, and the file
     /// pointer must be at the end of the file. If not, further operations may return Error
-    pub fn new(disk_log_file: fs::File) -> Self {
-        DiskLogWriter { disk_log_file }
+    ///
+    /// If the file is empty, a fresh header (magic + format version + `KEY_SIZE`) is written before
+    /// any records; an already non-empty file is assumed to already carry one (or to be a
+    /// headerless legacy log being appended to in place) and is left untouched.
+    pub fn new(mut disk_log_file: fs::File) -> Result<Self, Box<dyn Error>> {
+        if disk_log_file.metadata()?.len() == 0 {
+            write_header(&mut disk_log_file)?;
+        }
+        Ok(DiskLogWriter { disk_log_file })
     }
 
     /// Try write a log into the file
@@ -133,4 +200,309 @@ impl DiskLogWriter {
         self.disk_log_file.write(&msg.serialize())?;
         Ok(())
     }
+
+    /// Flush and fsync the underlying file, guaranteeing every previously written log is durable
+    pub fn sync(&self) -> Result<(), Box<dyn Error>> {
+        self.disk_log_file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Writes the current-format header (magic + format version + key size) at the file's current
+/// position, which must be its start.
+fn write_header(file: &mut fs::File) -> Result<(), Box<dyn Error>> {
+    file.write_all(LOG_MAGIC)?;
+    file.write_all(&LOG_FORMAT_VERSION.to_be_bytes())?;
+    file.write_all(&(KEY_SIZE as u16).to_be_bytes())?;
+    Ok(())
+}
+
+/// Validates the header at the start of `file`, leaving the file pointer positioned right after
+/// it so the caller can read records from there.
+///
+/// Empty files have nothing to validate. A file whose first bytes don't match `LOG_MAGIC` is
+/// treated as a headerless legacy log: the file pointer is rewound to the start (the `compat`
+/// path), so records are read exactly as they would have been before the header existed. A file
+/// that does start with `LOG_MAGIC` but carries a format version or key size this build doesn't
+/// understand is a hard error, since silently reading it would misinterpret the records.
+fn read_header(file: &mut fs::File) -> Result<(), Box<dyn Error>> {
+    if file.metadata()?.len() == 0 {
+        return Ok(());
+    }
+
+    let mut magic = [0u8; LOG_MAGIC.len()];
+    if file.read_exact(&mut magic).is_err() || &magic != LOG_MAGIC {
+        file.seek(SeekFrom::Start(0))?;
+        return Ok(());
+    }
+
+    let mut version = [0u8; 2];
+    file.read_exact(&mut version)?;
+    let version = u16::from_be_bytes(version);
+    if version != LOG_FORMAT_VERSION {
+        return Err(Box::new(DiskLogError::new(&format!("unsupported disk log format version {}, this build only understands {}", version, LOG_FORMAT_VERSION))));
+    }
+
+    let mut key_size = [0u8; 2];
+    file.read_exact(&mut key_size)?;
+    if u16::from_be_bytes(key_size) as usize != KEY_SIZE {
+        return Err(Box::new(DiskLogError::new("disk log record key size does not match this build")));
+    }
+
+    Ok(())
+}
+
+/// Upgrades a (possibly headerless) legacy-format log file at `path` in place, following the same
+/// read-everything-then-atomically-replace approach as `DiskLogCompactor::compact`: every record
+/// is read end-to-end and re-encoded under the current header and record layout into a temp file,
+/// which is fsync'd and only then renamed over `path`, so a crash mid-upgrade never loses or
+/// corrupts the original file.
+pub fn upgrade(path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut messages = Vec::new();
+    {
+        let mut reader = DiskLogReader::new(fs::File::open(path)?)?;
+        while let Some(message) = reader.next_log()? {
+            messages.push(message);
+        }
+    }
+
+    let tmp_path = path.with_extension("upgrade.tmp");
+    {
+        let tmp_file = fs::OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+        let mut writer = DiskLogWriter::new(tmp_file)?;
+        for message in messages {
+            writer.write(message)?;
+        }
+        writer.sync()?;
+    }
+
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Compacts a disk log down to its live set, following the log-structured/bitcask approach:
+/// stream the old file keeping only the latest record per key (a `Delete` simply drops the key,
+/// since absence is equivalent to deletion in a fresh file), then write the survivors to a new
+/// file and atomically replace the original with it.
+pub struct DiskLogCompactor;
+
+impl DiskLogCompactor {
+    /// Compact the log file at `path` in place.
+    ///
+    /// The original file is read in full to build the live set, the survivors are written to a
+    /// temp file next to `path`, fsync'd, and then renamed over `path`; a crash at any point before
+    /// the rename leaves the original file untouched. If `write_hint` is set, a `.hint` sidecar
+    /// file is also written, mapping each surviving key to its byte offset in the new log so a
+    /// future load can seek directly instead of rescanning.
+    pub fn compact(path: &Path, write_hint: bool) -> Result<(), Box<dyn Error>> {
+        let mut live: HashMap<[u8; KEY_SIZE], DiskLogMessage> = HashMap::new();
+        {
+            let mut reader = DiskLogReader::new(fs::File::open(path)?)?;
+            while let Some(message) = reader.next_log()? {
+                match message {
+                    DiskLogMessage::Put(key, value) => {
+                        live.insert(key.data, DiskLogMessage::Put(key, value));
+                    },
+                    DiskLogMessage::Delete(key) => {
+                        live.remove(&key.data);
+                    }
+                }
+            }
+        }
+
+        let tmp_path = path.with_extension("compact.tmp");
+        let mut hint: HashMap<Key, u64> = HashMap::new();
+        {
+            let tmp_file = fs::OpenOptions::new().write(true).create(true).truncate(true).open(&tmp_path)?;
+            let mut writer = DiskLogWriter::new(tmp_file)?;
+            // records start right after the header `DiskLogWriter::new` wrote to the empty file
+            let mut offset = (LOG_MAGIC.len() + 4) as u64;
+            for message in live.into_values() {
+                let key = match &message {
+                    DiskLogMessage::Put(key, _) => *key,
+                    DiskLogMessage::Delete(_) => unreachable!("delete records are never retained in the live set")
+                };
+                let len = message.serialize().len() as u64;
+                hint.insert(key, offset);
+                writer.write(message)?;
+                offset += len;
+            }
+            writer.sync()?;
+        }
+
+        fs::rename(&tmp_path, path)?;
+
+        if write_hint {
+            Self::write_hint_file(&path.with_extension("hint"), &hint)?;
+        }
+        Ok(())
+    }
+
+    fn write_hint_file(hint_path: &Path, offsets: &HashMap<Key, u64>) -> Result<(), Box<dyn Error>> {
+        let mut hint_file = fs::File::create(hint_path)?;
+        for (key, offset) in offsets.iter() {
+            hint_file.write(&key.serialize())?;
+            hint_file.write(&offset.to_be_bytes())?;
+        }
+        hint_file.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_compactor {
+    use crate::kvstorage::disklog::{DiskLogCompactor, DiskLogReader, DiskLogWriter, DiskLogMessage};
+    use crate::util::{gen_key_n, gen_value};
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn compact_keeps_only_latest_live_put() {
+        let path = Path::new("test_compact_basic.kv");
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(path.with_extension("hint"));
+
+        let key = gen_key_n(1);
+        let first_value = gen_value();
+        let second_value = gen_value();
+        {
+            let mut writer = DiskLogWriter::new(fs::File::create(path).unwrap()).unwrap();
+            writer.write(DiskLogMessage::Put(key, std::sync::Arc::new(first_value))).unwrap();
+            writer.write(DiskLogMessage::Put(key, std::sync::Arc::new(second_value))).unwrap();
+        }
+
+        DiskLogCompactor::compact(path, false).unwrap();
+
+        let mut reader = DiskLogReader::new(fs::File::open(path).unwrap()).unwrap();
+        let mut records = Vec::new();
+        while let Some(msg) = reader.next_log().unwrap() {
+            records.push(msg);
+        }
+        assert_eq!(records.len(), 1);
+        match &records[0] {
+            DiskLogMessage::Put(k, v) => {
+                assert_eq!(*k, key);
+                assert_eq!(**v, second_value);
+            },
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn compact_drops_deleted_keys() {
+        let path = Path::new("test_compact_delete.kv");
+        let _ = fs::remove_file(path);
+        let _ = fs::remove_file(path.with_extension("hint"));
+
+        let survivor = gen_key_n(2);
+        let deleted = gen_key_n(3);
+        {
+            let mut writer = DiskLogWriter::new(fs::File::create(path).unwrap()).unwrap();
+            writer.write(DiskLogMessage::Put(survivor, std::sync::Arc::new(gen_value()))).unwrap();
+            writer.write(DiskLogMessage::Put(deleted, std::sync::Arc::new(gen_value()))).unwrap();
+            writer.write(DiskLogMessage::Delete(deleted)).unwrap();
+        }
+
+        DiskLogCompactor::compact(path, false).unwrap();
+
+        let mut reader = DiskLogReader::new(fs::File::open(path).unwrap()).unwrap();
+        let mut records = Vec::new();
+        while let Some(msg) = reader.next_log().unwrap() {
+            records.push(msg);
+        }
+        assert_eq!(records.len(), 1);
+        match &records[0] {
+            DiskLogMessage::Put(k, _) => assert_eq!(*k, survivor),
+            _ => panic!()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_header {
+    use crate::kvstorage::disklog::{self, DiskLogReader, DiskLogWriter, DiskLogMessage};
+    use crate::kvstorage::Serializable;
+    use crate::util::{gen_key_n, gen_value};
+    use std::fs;
+    use std::io::Read;
+    use std::path::Path;
+
+    #[test]
+    fn writer_prepends_header_and_reader_validates_it() {
+        let path = Path::new("test_header_roundtrip.kv");
+        let _ = fs::remove_file(path);
+
+        let key = gen_key_n(1);
+        let value = gen_value();
+        {
+            let mut writer = DiskLogWriter::new(fs::File::create(path).unwrap()).unwrap();
+            writer.write(DiskLogMessage::Put(key, std::sync::Arc::new(value))).unwrap();
+        }
+
+        let mut raw = Vec::new();
+        fs::File::open(path).unwrap().read_to_end(&mut raw).unwrap();
+        assert_eq!(&raw[0..5], b"KVLOG");
+
+        let mut reader = DiskLogReader::new(fs::File::open(path).unwrap()).unwrap();
+        match reader.next_log().unwrap() {
+            Some(DiskLogMessage::Put(k, v)) => {
+                assert_eq!(k, key);
+                assert_eq!(*v, value);
+            },
+            _ => panic!()
+        }
+        assert!(reader.next_log().unwrap().is_none());
+    }
+
+    #[test]
+    fn reader_accepts_headerless_legacy_log() {
+        let path = Path::new("test_header_legacy.kv");
+        let _ = fs::remove_file(path);
+
+        let key = gen_key_n(2);
+        let value = gen_value();
+        let mut legacy = vec![b'P'];
+        legacy.extend_from_slice(&key.serialize());
+        value.write_to(&mut legacy);
+        fs::write(path, &legacy).unwrap();
+
+        let mut reader = DiskLogReader::new(fs::File::open(path).unwrap()).unwrap();
+        match reader.next_log().unwrap() {
+            Some(DiskLogMessage::Put(k, v)) => {
+                assert_eq!(k, key);
+                assert_eq!(*v, value);
+            },
+            _ => panic!()
+        }
+        assert!(reader.next_log().unwrap().is_none());
+    }
+
+    #[test]
+    fn upgrade_rewrites_legacy_log_with_current_header() {
+        let path = Path::new("test_header_upgrade.kv");
+        let _ = fs::remove_file(path);
+
+        let key = gen_key_n(3);
+        let value = gen_value();
+        let mut legacy = vec![b'P'];
+        legacy.extend_from_slice(&key.serialize());
+        value.write_to(&mut legacy);
+        fs::write(path, &legacy).unwrap();
+
+        disklog::upgrade(path).unwrap();
+
+        let mut raw = Vec::new();
+        fs::File::open(path).unwrap().read_to_end(&mut raw).unwrap();
+        assert_eq!(&raw[0..5], b"KVLOG");
+
+        let mut reader = DiskLogReader::new(fs::File::open(path).unwrap()).unwrap();
+        match reader.next_log().unwrap() {
+            Some(DiskLogMessage::Put(k, v)) => {
+                assert_eq!(k, key);
+                assert_eq!(*v, value);
+            },
+            _ => panic!()
+        }
+        assert!(reader.next_log().unwrap().is_none());
+    }
 }